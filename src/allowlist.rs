@@ -0,0 +1,96 @@
+//! Merkle tree of the wallets allowed to buy from a market with an allowlist.
+//!
+//! A leaf is the sha256 hash of a zero byte, the wallet's pubkey and the borsh encoded
+//! allocation, the total number of tokens the wallet may buy. A node is the hash of a one byte
+//! and its children in ascending order, so proofs don't have to tell left from right.
+//! A layer with an odd number of nodes carries its last node up unchanged.
+//!
+//! The market stores only the root, [`AllowlistTree`] builds the tree and proofs off-chain.
+
+use borsh::BorshSerialize;
+use solana_program::{hash::hashv, pubkey::Pubkey};
+
+const LEAF_PREFIX: &[u8] = &[0];
+const NODE_PREFIX: &[u8] = &[1];
+
+/// A wallet allowed to buy, at most `allocation` tokens if it's set
+#[derive(BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub struct AllowlistEntry {
+    pub wallet: Pubkey,
+    pub allocation: Option<u64>,
+}
+
+impl AllowlistEntry {
+    pub fn leaf(&self) -> [u8; 32] {
+        let allocation = self.allocation.try_to_vec().unwrap();
+        hashv(&[LEAF_PREFIX, self.wallet.as_ref(), &allocation]).to_bytes()
+    }
+}
+
+fn node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let (first, second) = if left <= right {
+        (left, right)
+    } else {
+        (right, left)
+    };
+    hashv(&[NODE_PREFIX, first, second]).to_bytes()
+}
+
+/// Whether `proof` leads from `leaf` to `root`
+pub fn verify(root: &[u8; 32], leaf: [u8; 32], proof: &[[u8; 32]]) -> bool {
+    let computed = proof
+        .iter()
+        .fold(leaf, |hash, sibling| node(&hash, sibling));
+    computed == *root
+}
+
+/// Tree of allowlist entries, the leaves keep the entries' order
+pub struct AllowlistTree {
+    /// Layers from the leaves up to the root
+    layers: Vec<Vec<[u8; 32]>>,
+}
+
+impl AllowlistTree {
+    /// Tree of `entries`, which must not be empty
+    pub fn new(entries: &[AllowlistEntry]) -> Self {
+        assert!(!entries.is_empty(), "The allowlist is empty");
+
+        let mut layers = vec![entries.iter().map(AllowlistEntry::leaf).collect::<Vec<_>>()];
+        while layers.last().unwrap().len() > 1 {
+            let next = layers
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node(left, right),
+                    [last] => *last,
+                    _ => unreachable!(),
+                })
+                .collect();
+            layers.push(next);
+        }
+
+        AllowlistTree { layers }
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.layers.last().unwrap()[0]
+    }
+
+    /// Proof of the entry at `index`, `None` if there is no such entry
+    pub fn proof(&self, mut index: usize) -> Option<Vec<[u8; 32]>> {
+        if index >= self.layers[0].len() {
+            return None;
+        }
+
+        let mut proof = vec![];
+        for layer in &self.layers[..self.layers.len() - 1] {
+            if let Some(sibling) = layer.get(index ^ 1) {
+                proof.push(*sibling);
+            }
+            index /= 2;
+        }
+
+        Some(proof)
+    }
+}
@@ -22,6 +22,113 @@ pub enum MarketError {
 
     #[error("The market doesn't support the mint")]
     UnsupportedMint,
+
+    #[error("The pubkey of the market's lamports account is different from PDA")]
+    LamportsPubkeyMismatch,
+
+    #[error("The market must keep enough lamports to buy back tokens in circulation")]
+    InsufficientReserve,
+
+    #[error("The price of the transaction is beyond the client's limit")]
+    PriceLimitExceeded,
+
+    #[error("The fee can't exceed 100%")]
+    InvalidFee,
+
+    #[error("The fee recipient is different from the market's one")]
+    FeeRecipientMismatch,
+
+    #[error("The market is paused")]
+    MarketPaused,
+
+    #[error("The signer is not the proposed admin")]
+    PendingAdminMismatch,
+
+    #[error("The pubkey of the market's quote vault is different from PDA")]
+    QuoteVaultPubkeyMismatch,
+
+    #[error("The market can't buy back more tokens than it sold on the price curve")]
+    CurveSupplyExceeded,
+
+    #[error("The market's reserves can't cover the transaction")]
+    InsufficientLiquidity,
+
+    #[error("The pubkey of the oracle account is different from the market's one")]
+    OraclePubkeyMismatch,
+
+    #[error("The oracle price is too old")]
+    StaleOraclePrice,
+
+    #[error("The quote isn't signed by the market's admin")]
+    InvalidQuoteSignature,
+
+    #[error("The quote has expired")]
+    QuoteExpired,
+
+    #[error("The quote doesn't cover so many tokens")]
+    QuoteTokensExceeded,
+
+    #[error("The quote has already been used")]
+    QuoteNonceUsed,
+
+    #[error("The sale hasn't opened yet")]
+    SaleNotOpen,
+
+    #[error("The sale has closed")]
+    SaleClosed,
+
+    #[error("The market's sale cap has been reached")]
+    SaleCapExceeded,
+
+    #[error("The buyer's cap has been reached")]
+    BuyerCapExceeded,
+
+    #[error("The client isn't on the market's allowlist")]
+    NotAllowlisted,
+
+    #[error("The client's wallet is blocked")]
+    WalletBlocked,
+
+    #[error("Not enough signers of the admin multisig")]
+    NotEnoughAdminSigners,
+
+    #[error("The price change exceeds the market's price band")]
+    PriceChangeOutOfBand,
+
+    #[error("The price was updated too recently")]
+    PriceUpdateTooSoon,
+
+    #[error(
+        "The sell price can't exceed the buy price or come closer to it than the market allows"
+    )]
+    InvalidSpread,
+
+    #[error("The market's settings account isn't owned by the program")]
+    SettingsOwnerMismatch,
+
+    #[error("The token program account is not the token program")]
+    TokenProgramMismatch,
+
+    #[error("The system program account is not the system program")]
+    SystemProgramMismatch,
+
+    #[error("The instructions sysvar account is not the instructions sysvar")]
+    InstructionsSysvarMismatch,
+
+    #[error("The pubkey of the quote's nonce record is different from PDA")]
+    NoncePubkeyMismatch,
+
+    #[error("The pubkey of the client's block record is different from PDA")]
+    BlockRecordPubkeyMismatch,
+
+    #[error("The pubkey of the client's buyer record is different from PDA")]
+    BuyerRecordPubkeyMismatch,
+
+    #[error("The rent sysvar account is not the rent sysvar")]
+    RentSysvarMismatch,
+
+    #[error("The mint account is not owned by the token program")]
+    MintOwnerMismatch,
 }
 
 impl From<MarketError> for ProgramError {
@@ -1,4 +1,8 @@
-use crate::{id, state::MarketSettings};
+use crate::{
+    id,
+    secp256k1::ETH_ADDRESS_BYTES,
+    state::{AdminMultisig, MarketSettings, PriceBand, PricingMode, SaleLimits},
+};
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     instruction::{AccountMeta, Instruction},
@@ -6,15 +10,73 @@ use solana_program::{
     system_program, sysvar,
 };
 
+/// Prices of `price_denominator` whole tokens, a buy costs at least
+/// and a sell pays at most the exact price of the tokens
 #[derive(BorshSerialize, BorshDeserialize, Clone)]
 pub struct PriceArgs {
     pub sell_price: u64,
     pub buy_price: u64,
+    pub price_denominator: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct FeeArgs {
+    /// Fee in basis points of the transaction's price, up to 10000
+    pub fee_basis_points: u16,
+    pub fee_recipient: Pubkey,
+}
+
+/// Keys of the roles delegated by the market's owner
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct RolesArgs {
+    pub price_authority: Pubkey,
+    pub treasury_authority: Pubkey,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct InitializeArgs {
+    pub prices: PriceArgs,
+    pub fee: FeeArgs,
+    /// Forbid lamports withdrawals that would leave the market unable to buy
+    /// back every token in circulation at the current sell price
+    pub reserve_sells: bool,
+    /// Mint of the token the market is paid in, native SOL if `None`
+    pub quote_mint: Option<Pubkey>,
+    pub pricing: PricingMode,
+    pub limits: SaleLimits,
+    /// The smallest difference between the buy and the sell prices,
+    /// in basis points of the buy price
+    pub min_spread_basis_points: u16,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Clone)]
 pub struct TokensNumber(pub u64);
 
+/// Price offered by the admin off-chain with the market's quote key,
+/// valid until `expiry_slot` and usable once by a single client
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SignedQuote {
+    /// Price of `price_denominator` whole tokens
+    pub price: u64,
+    pub max_tokens: u64,
+    pub expiry_slot: u64,
+    pub nonce: u64,
+}
+
+impl SignedQuote {
+    /// The message signed with the quote key: the market's settings PDA, which is unique
+    /// to the program and the mint, and the client followed by the little endian price,
+    /// max tokens, expiry slot and nonce
+    pub fn message(&self, settings: &Pubkey, client: &Pubkey) -> Vec<u8> {
+        let mut message = settings.to_bytes().to_vec();
+        message.extend_from_slice(client.as_ref());
+        for field in [self.price, self.max_tokens, self.expiry_slot, self.nonce].iter() {
+            message.extend_from_slice(&field.to_le_bytes());
+        }
+        message
+    }
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Clone)]
 pub enum MarketInstructions {
     /// Initialize store
@@ -26,12 +88,17 @@ pub enum MarketInstructions {
     /// 4. `[]` Token program
     /// 5. `[]` System program
     /// 6. `[]` Rent sysvar
-    InitializeStore(PriceArgs),
+    /// 7. `[writable]` Market's quote vault, PDA, only with a quote mint
+    /// 8. `[]` Quote mint, only with a quote mint
+    InitializeStore(InitializeArgs),
 
-    /// Update price
+    /// Update price, with a price timelock the new prices take effect once it passes.
+    /// With a price band the prices can't move further than it allows.
+    /// The sell price can't exceed the buy price or come closer to it than the minimum spread
     ///
-    /// 0. `[signer]` Market's admin
+    /// 0. `[signer]` Market's price authority, a multisig signer if the admin keeps it
     /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
     UpdatePrice(PriceArgs),
 
     /// Sell
@@ -39,10 +106,17 @@ pub enum MarketInstructions {
     /// 0. `[signer, writable]` Client's account
     /// 1. `[writable]` Client's token account
     /// 2. `[writable]` Market's account with lamports, PDA
-    /// 3. `[]` Market's account with settings, PDA
+    /// 3. `[writable]` Market's account with settings, PDA
     /// 4. `[writable]` Market's token account, PDA
     /// 5. `[]` Token program
     /// 6. `[]` System program
+    /// 7. `[writable]` Fee recipient
+    /// 8. `[]` Client's block record, PDA, empty unless the client is blocked
+    /// 9. `[writable]` Client's quote token account, only with a quote mint
+    /// 10. `[writable]` Market's quote vault, PDA, only with a quote mint
+    /// 11. `[]` Oracle account, follows the accounts above, only in the oracle pricing mode
+    /// 12. `[writable]` Client's buyer record, PDA, follows the accounts above,
+    ///     only with a per-buyer limit
     Sell(TokensNumber),
 
     /// Buy
@@ -50,42 +124,543 @@ pub enum MarketInstructions {
     /// 0. `[signer, writable]` Client's account
     /// 1. `[writable]` Client's token account
     /// 2. `[writable]` Market's account with lamports, PDA
-    /// 3. `[]` Market's account with settings, PDA
+    /// 3. `[writable]` Market's account with settings, PDA
     /// 4. `[writable]` Market's token account, PDA
     /// 5. `[]` Token program
     /// 6. `[]` System program
+    /// 7. `[writable]` Fee recipient
+    /// 8. `[]` Client's block record, PDA, empty unless the client is blocked
+    /// 9. `[writable]` Client's quote token account, only with a quote mint
+    /// 10. `[writable]` Market's quote vault, PDA, only with a quote mint
+    /// 11. `[]` Oracle account, follows the accounts above, only in the oracle pricing mode
+    /// 12. `[writable]` Client's buyer record, PDA, follows the accounts above,
+    ///     only with a per-buyer limit
     Buy(TokensNumber),
+
+    /// Withdraw lamports from the market's account with lamports
+    /// or quote tokens from the quote vault if the market has a quote mint
+    ///
+    /// 0. `[signer]` Market's treasury authority, a multisig signer if the admin keeps it
+    /// 1. `[]` Market's account with settings, PDA
+    /// 2. `[writable]` Market's account with lamports, PDA
+    /// 3. `[writable]` Destination account, a quote token account with a quote mint
+    /// 4. `[]` Market's token account, PDA
+    /// 5. `[]` Mint account
+    /// 6. `[]` System program
+    /// 7. `[writable]` Market's quote vault, PDA
+    /// 8. `[]` Token program
+    /// 9. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    WithdrawLamports { amount: u64 },
+
+    /// Withdraw tokens from the market's token account
+    ///
+    /// 0. `[signer]` Market's treasury authority, a multisig signer if the admin keeps it
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[writable]` Market's token account, PDA
+    /// 3. `[writable]` Destination token account
+    /// 4. `[]` Token program
+    /// 5. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    WithdrawTokens { amount: u64, destination: Pubkey },
+
+    /// Deposit tokens to the market's token account
+    ///
+    /// 0. `[signer]` Market's treasury authority, a multisig signer if the admin keeps it
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[writable]` Treasury authority's token account
+    /// 3. `[writable]` Market's token account, PDA
+    /// 4. `[]` Token program
+    /// 5. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    DepositTokens { amount: u64 },
+
+    /// Close market, withdraw its tokens and lamports and reclaim rent
+    ///
+    /// 0. `[signer, writable]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[writable]` Market's token account, PDA
+    /// 3. `[writable]` Market's account with lamports, PDA
+    /// 4. `[writable]` Destination token account
+    /// 5. `[writable]` Destination account for lamports
+    /// 6. `[]` Token program
+    /// 7. `[]` System program
+    /// 8. `[writable]` Market's quote vault, PDA, only with a quote mint
+    /// 9. `[writable]` Destination quote token account, only with a quote mint
+    /// 10. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    CloseMarket,
+
+    /// Buy if the total price with the fee doesn't exceed `max_lamports`
+    ///
+    /// 0. `[signer, writable]` Client's account
+    /// 1. `[writable]` Client's token account
+    /// 2. `[writable]` Market's account with lamports, PDA
+    /// 3. `[writable]` Market's account with settings, PDA
+    /// 4. `[writable]` Market's token account, PDA
+    /// 5. `[]` Token program
+    /// 6. `[]` System program
+    /// 7. `[writable]` Fee recipient
+    /// 8. `[]` Client's block record, PDA, empty unless the client is blocked
+    /// 9. `[writable]` Client's quote token account, only with a quote mint
+    /// 10. `[writable]` Market's quote vault, PDA, only with a quote mint
+    /// 11. `[]` Oracle account, follows the accounts above, only in the oracle pricing mode
+    /// 12. `[writable]` Client's buyer record, PDA, follows the accounts above,
+    ///     only with a per-buyer limit
+    BuyWithLimit { tokens: u64, max_lamports: u64 },
+
+    /// Sell if the total price without the fee isn't less than `min_lamports`
+    ///
+    /// 0. `[signer, writable]` Client's account
+    /// 1. `[writable]` Client's token account
+    /// 2. `[writable]` Market's account with lamports, PDA
+    /// 3. `[writable]` Market's account with settings, PDA
+    /// 4. `[writable]` Market's token account, PDA
+    /// 5. `[]` Token program
+    /// 6. `[]` System program
+    /// 7. `[writable]` Fee recipient
+    /// 8. `[]` Client's block record, PDA, empty unless the client is blocked
+    /// 9. `[writable]` Client's quote token account, only with a quote mint
+    /// 10. `[writable]` Market's quote vault, PDA, only with a quote mint
+    /// 11. `[]` Oracle account, follows the accounts above, only in the oracle pricing mode
+    /// 12. `[writable]` Client's buyer record, PDA, follows the accounts above,
+    ///     only with a per-buyer limit
+    SellWithLimit { tokens: u64, min_lamports: u64 },
+
+    /// Buy as many tokens as `lamports` can pay for including the fee,
+    /// the change stays with the client
+    ///
+    /// 0. `[signer, writable]` Client's account
+    /// 1. `[writable]` Client's token account
+    /// 2. `[writable]` Market's account with lamports, PDA
+    /// 3. `[writable]` Market's account with settings, PDA
+    /// 4. `[writable]` Market's token account, PDA
+    /// 5. `[]` Token program
+    /// 6. `[]` System program
+    /// 7. `[writable]` Fee recipient
+    /// 8. `[]` Client's block record, PDA, empty unless the client is blocked
+    /// 9. `[writable]` Client's quote token account, only with a quote mint
+    /// 10. `[writable]` Market's quote vault, PDA, only with a quote mint
+    /// 11. `[]` Oracle account, follows the accounts above, only in the oracle pricing mode
+    /// 12. `[writable]` Client's buyer record, PDA, follows the accounts above,
+    ///     only with a per-buyer limit
+    BuyExactLamports { lamports: u64, min_tokens: u64 },
+
+    /// Update fee
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    UpdateFee(FeeArgs),
+
+    /// Stop trading until the market is resumed, admin instructions are still allowed
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    Pause,
+
+    /// Resume trading
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    Resume,
+
+    /// Propose a new admin, who has to accept it with `AcceptAdmin`
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    ProposeAdmin { new_admin: Pubkey },
+
+    /// Become the market's admin, the admin multisig and the quote key are removed
+    ///
+    /// 0. `[signer]` Proposed admin
+    /// 1. `[writable]` Market's account with settings, PDA
+    AcceptAdmin,
+
+    /// Cancel the proposed admin transfer
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    CancelAdminTransfer,
+
+    /// Buy tokens for the price of a quote signed with the market's quote key, the instruction
+    /// right before this one must be the secp256k1 verification of the quote's message.
+    /// Quotes are disabled while the market has no quote key
+    ///
+    /// 0. `[signer, writable]` Client's account
+    /// 1. `[writable]` Client's token account
+    /// 2. `[writable]` Market's account with lamports, PDA
+    /// 3. `[writable]` Market's account with settings, PDA
+    /// 4. `[writable]` Market's token account, PDA
+    /// 5. `[]` Token program
+    /// 6. `[]` System program
+    /// 7. `[writable]` Fee recipient
+    /// 8. `[]` Client's block record, PDA, empty unless the client is blocked
+    /// 9. `[writable]` Client's quote token account, only with a quote mint
+    /// 10. `[writable]` Market's quote vault, PDA, only with a quote mint
+    /// 11. `[]` Oracle account, follows the accounts above, only in the oracle pricing mode
+    /// 12. `[writable]` Client's buyer record, PDA, follows the accounts above,
+    ///     only with a per-buyer limit
+    /// 13. `[]` Instructions sysvar, follows the accounts above
+    /// 14. `[writable]` Record of the quote's nonce, PDA
+    BuyWithSignedQuote { tokens: u64, quote: SignedQuote },
+
+    /// Update the schedule and limits of the market's sales
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    UpdateSaleLimits(SaleLimits),
+
+    /// Set the root of the Merkle tree of wallets allowed to buy,
+    /// `None` lets anyone buy with the other buy instructions
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    UpdateAllowlist { root: Option<[u8; 32]> },
+
+    /// Buy as a wallet of the market's allowlist, proven by `proof` of the client's
+    /// pubkey and `allocation`, the total number of tokens they may buy
+    ///
+    /// 0. `[signer, writable]` Client's account
+    /// 1. `[writable]` Client's token account
+    /// 2. `[writable]` Market's account with lamports, PDA
+    /// 3. `[writable]` Market's account with settings, PDA
+    /// 4. `[writable]` Market's token account, PDA
+    /// 5. `[]` Token program
+    /// 6. `[]` System program
+    /// 7. `[writable]` Fee recipient
+    /// 8. `[]` Client's block record, PDA, empty unless the client is blocked
+    /// 9. `[writable]` Client's quote token account, only with a quote mint
+    /// 10. `[writable]` Market's quote vault, PDA, only with a quote mint
+    /// 11. `[]` Oracle account, follows the accounts above, only in the oracle pricing mode
+    /// 12. `[writable]` Client's buyer record, PDA, follows the accounts above,
+    ///     only with a per-buyer limit or an allocation
+    BuyWithProof {
+        tokens: u64,
+        allocation: Option<u64>,
+        proof: Vec<[u8; 32]>,
+    },
+
+    /// Block the wallet from buying and selling, creating its block record
+    ///
+    /// 0. `[signer, writable]` Market's admin or a signer of the admin multisig
+    /// 1. `[]` Market's account with settings, PDA
+    /// 2. `[writable]` Wallet's block record, PDA
+    /// 3. `[]` System program
+    /// 4. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    BlockWallet { wallet: Pubkey },
+
+    /// Let the wallet trade again, closing its block record
+    ///
+    /// 0. `[signer, writable]` Market's admin or a signer of the admin multisig
+    /// 1. `[]` Market's account with settings, PDA
+    /// 2. `[writable]` Wallet's block record, PDA
+    /// 3. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    UnblockWallet { wallet: Pubkey },
+
+    /// Set the M-of-N signer set administering the market, `None` leaves it to the admin alone
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    SetAdminMultisig(Option<AdminMultisig>),
+
+    /// Assign the roles delegated by the owner, the market's admin
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    UpdateRoles(RolesArgs),
+
+    /// Set the seconds between a price update and the new prices taking effect,
+    /// `None` makes price updates take effect immediately. A longer timelock is set at once,
+    /// a shorter one or its removal only once the current timelock passes
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    UpdatePriceTimelock { price_timelock: Option<i64> },
+
+    /// Cancel the price update which hasn't taken effect yet
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    CancelPriceUpdate,
+
+    /// Set the limits of each price update, `None` removes them
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    UpdatePriceBand(Option<PriceBand>),
+
+    /// Set the Ethereum address of the secp256k1 key the admin signs quotes with,
+    /// `None` disables quotes
+    ///
+    /// 0. `[signer]` Market's admin or a signer of the admin multisig
+    /// 1. `[writable]` Market's account with settings, PDA
+    /// 2. `[signer]` Other signers of the admin multisig from here on, only with a multisig
+    UpdateQuoteSigner {
+        quote_signer: Option<[u8; ETH_ADDRESS_BYTES]>,
+    },
 }
 
 impl MarketInstructions {
-    pub fn initialize_store(admin: &Pubkey, mint: &Pubkey, args: PriceArgs) -> Instruction {
-        let market_pubkey = MarketSettings::settings_pubkey_with_bump().0;
-        let market_token_pubkey = MarketSettings::token_pubkey_with_bump().0;
+    pub fn initialize_store(admin: &Pubkey, mint: &Pubkey, args: InitializeArgs) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+        let market_token_pubkey = MarketSettings::token_pubkey_with_bump(mint).0;
         let token_program = spl_token::id();
         let system_program = system_program::id();
         let rent_sysvar = sysvar::rent::id();
 
+        let mut accounts = vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(market_pubkey, false),
+            AccountMeta::new(market_token_pubkey, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(system_program, false),
+            AccountMeta::new_readonly(rent_sysvar, false),
+        ];
+        if let Some(quote_mint) = args.quote_mint {
+            let quote_vault_pubkey = MarketSettings::quote_vault_pubkey_with_bump(mint).0;
+            accounts.push(AccountMeta::new(quote_vault_pubkey, false));
+            accounts.push(AccountMeta::new_readonly(quote_mint, false));
+        }
+
+        Instruction::new_with_borsh(id(), &MarketInstructions::InitializeStore(args), accounts)
+    }
+
+    pub fn update_price(price_authority: &Pubkey, mint: &Pubkey, args: PriceArgs) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::UpdatePrice(args),
+            vec![
+                AccountMeta::new_readonly(*price_authority, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn update_price_timelock(
+        admin: &Pubkey,
+        mint: &Pubkey,
+        price_timelock: Option<i64>,
+    ) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::UpdatePriceTimelock { price_timelock },
+            vec![
+                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn update_price_band(
+        admin: &Pubkey,
+        mint: &Pubkey,
+        price_band: Option<PriceBand>,
+    ) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::UpdatePriceBand(price_band),
+            vec![
+                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn cancel_price_update(admin: &Pubkey, mint: &Pubkey) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::CancelPriceUpdate,
+            vec![
+                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn update_roles(admin: &Pubkey, mint: &Pubkey, args: RolesArgs) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::UpdateRoles(args),
+            vec![
+                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn update_fee(admin: &Pubkey, mint: &Pubkey, args: FeeArgs) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::UpdateFee(args),
+            vec![
+                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn update_sale_limits(admin: &Pubkey, mint: &Pubkey, limits: SaleLimits) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::UpdateSaleLimits(limits),
+            vec![
+                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn update_allowlist(admin: &Pubkey, mint: &Pubkey, root: Option<[u8; 32]>) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::UpdateAllowlist { root },
+            vec![
+                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn update_quote_signer(
+        admin: &Pubkey,
+        mint: &Pubkey,
+        quote_signer: Option<[u8; ETH_ADDRESS_BYTES]>,
+    ) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::UpdateQuoteSigner { quote_signer },
+            vec![
+                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn block_wallet(admin: &Pubkey, mint: &Pubkey, wallet: &Pubkey) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+        let block_record_pubkey = MarketSettings::block_record_pubkey_with_bump(mint, wallet).0;
+
         Instruction::new_with_borsh(
             id(),
-            &MarketInstructions::InitializeStore(args),
+            &MarketInstructions::BlockWallet { wallet: *wallet },
             vec![
                 AccountMeta::new(*admin, true),
+                AccountMeta::new_readonly(market_pubkey, false),
+                AccountMeta::new(block_record_pubkey, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        )
+    }
+
+    pub fn unblock_wallet(admin: &Pubkey, mint: &Pubkey, wallet: &Pubkey) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+        let block_record_pubkey = MarketSettings::block_record_pubkey_with_bump(mint, wallet).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::UnblockWallet { wallet: *wallet },
+            vec![
+                AccountMeta::new(*admin, true),
+                AccountMeta::new_readonly(market_pubkey, false),
+                AccountMeta::new(block_record_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn pause(admin: &Pubkey, mint: &Pubkey) -> Instruction {
+        Self::pause_resume_common(admin, mint, &MarketInstructions::Pause)
+    }
+
+    pub fn resume(admin: &Pubkey, mint: &Pubkey) -> Instruction {
+        Self::pause_resume_common(admin, mint, &MarketInstructions::Resume)
+    }
+
+    fn pause_resume_common(
+        admin: &Pubkey,
+        mint: &Pubkey,
+        data: &MarketInstructions,
+    ) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            data,
+            vec![
+                AccountMeta::new_readonly(*admin, true),
                 AccountMeta::new(market_pubkey, false),
-                AccountMeta::new(market_token_pubkey, false),
-                AccountMeta::new_readonly(*mint, false),
-                AccountMeta::new_readonly(token_program, false),
-                AccountMeta::new_readonly(system_program, false),
-                AccountMeta::new_readonly(rent_sysvar, false),
             ],
         )
     }
 
-    pub fn update_price(admin: &Pubkey, args: PriceArgs) -> Instruction {
-        let market_pubkey = MarketSettings::settings_pubkey_with_bump().0;
+    pub fn propose_admin(admin: &Pubkey, mint: &Pubkey, new_admin: &Pubkey) -> Instruction {
+        let data = MarketInstructions::ProposeAdmin {
+            new_admin: *new_admin,
+        };
+        Self::admin_transfer_common(admin, mint, &data)
+    }
+
+    pub fn accept_admin(new_admin: &Pubkey, mint: &Pubkey) -> Instruction {
+        Self::admin_transfer_common(new_admin, mint, &MarketInstructions::AcceptAdmin)
+    }
+
+    pub fn cancel_admin_transfer(admin: &Pubkey, mint: &Pubkey) -> Instruction {
+        Self::admin_transfer_common(admin, mint, &MarketInstructions::CancelAdminTransfer)
+    }
+
+    fn admin_transfer_common(
+        signer: &Pubkey,
+        mint: &Pubkey,
+        data: &MarketInstructions,
+    ) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
 
         Instruction::new_with_borsh(
             id(),
-            &MarketInstructions::UpdatePrice(args),
+            data,
+            vec![
+                AccountMeta::new_readonly(*signer, true),
+                AccountMeta::new(market_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn set_admin_multisig(
+        admin: &Pubkey,
+        mint: &Pubkey,
+        multisig: Option<AdminMultisig>,
+    ) -> Instruction {
+        let market_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::SetAdminMultisig(multisig),
             vec![
                 AccountMeta::new_readonly(*admin, true),
                 AccountMeta::new(market_pubkey, false),
@@ -93,39 +668,297 @@ impl MarketInstructions {
         )
     }
 
+    /// Adds the other signers of the admin multisig to an admin instruction
+    /// or to an instruction of a role the admin keeps
+    pub fn with_admin_signers(mut instruction: Instruction, signers: &[&Pubkey]) -> Instruction {
+        instruction.accounts.extend(
+            signers
+                .iter()
+                .map(|signer| AccountMeta::new_readonly(**signer, true)),
+        );
+        instruction
+    }
+
+    /// `client_quote` is the client's token account of the quote mint,
+    /// required if the market has one, panics without it
     fn sell_buy_common(
         client: &Pubkey,
         client_token: &Pubkey,
+        client_quote: Option<&Pubkey>,
+        settings: &MarketSettings,
         data: &MarketInstructions,
     ) -> Instruction {
-        let market_settings_pubkey = MarketSettings::settings_pubkey_with_bump().0;
-        let market_token_pubkey = MarketSettings::token_pubkey_with_bump().0;
-        let market_lamports_pubkey = MarketSettings::lamports_account_pubkey().0;
+        let mint = &settings.mint;
+        let market_settings_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+        let market_token_pubkey = MarketSettings::token_pubkey_with_bump(mint).0;
+        let market_lamports_pubkey = MarketSettings::lamports_account_pubkey(mint).0;
+        let block_record_pubkey = MarketSettings::block_record_pubkey_with_bump(mint, client).0;
+        let token_program = spl_token::id();
+        let system_program = system_program::id();
+
+        let mut accounts = vec![
+            AccountMeta::new(*client, true),
+            AccountMeta::new(*client_token, false),
+            AccountMeta::new(market_lamports_pubkey, false),
+            AccountMeta::new(market_settings_pubkey, false),
+            AccountMeta::new(market_token_pubkey, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(system_program, false),
+            AccountMeta::new(settings.fee_recipient, false),
+            AccountMeta::new_readonly(block_record_pubkey, false),
+        ];
+        if settings.quote_mint.is_some() {
+            let client_quote = client_quote
+                .expect("The market has a quote mint, the client's quote account is required");
+            let quote_vault_pubkey = MarketSettings::quote_vault_pubkey_with_bump(mint).0;
+            accounts.push(AccountMeta::new(*client_quote, false));
+            accounts.push(AccountMeta::new(quote_vault_pubkey, false));
+        }
+        if let PricingMode::Oracle { oracle, .. } = settings.pricing {
+            accounts.push(AccountMeta::new_readonly(oracle, false));
+        }
+        if settings.limits.max_tokens_per_buyer.is_some() {
+            let buyer_record_pubkey = MarketSettings::buyer_record_pubkey_with_bump(mint, client).0;
+            accounts.push(AccountMeta::new(buyer_record_pubkey, false));
+        }
+
+        Instruction::new_with_borsh(id(), data, accounts)
+    }
+
+    pub fn sell(
+        client: &Pubkey,
+        client_token: &Pubkey,
+        client_quote: Option<&Pubkey>,
+        settings: &MarketSettings,
+        args: TokensNumber,
+    ) -> Instruction {
+        let data = MarketInstructions::Sell(args);
+        MarketInstructions::sell_buy_common(client, client_token, client_quote, settings, &data)
+    }
+
+    pub fn buy(
+        client: &Pubkey,
+        client_token: &Pubkey,
+        client_quote: Option<&Pubkey>,
+        settings: &MarketSettings,
+        args: TokensNumber,
+    ) -> Instruction {
+        let data = MarketInstructions::Buy(args);
+        MarketInstructions::sell_buy_common(client, client_token, client_quote, settings, &data)
+    }
+
+    pub fn sell_with_limit(
+        client: &Pubkey,
+        client_token: &Pubkey,
+        client_quote: Option<&Pubkey>,
+        settings: &MarketSettings,
+        tokens: u64,
+        min_lamports: u64,
+    ) -> Instruction {
+        let data = MarketInstructions::SellWithLimit {
+            tokens,
+            min_lamports,
+        };
+        MarketInstructions::sell_buy_common(client, client_token, client_quote, settings, &data)
+    }
+
+    pub fn buy_with_limit(
+        client: &Pubkey,
+        client_token: &Pubkey,
+        client_quote: Option<&Pubkey>,
+        settings: &MarketSettings,
+        tokens: u64,
+        max_lamports: u64,
+    ) -> Instruction {
+        let data = MarketInstructions::BuyWithLimit {
+            tokens,
+            max_lamports,
+        };
+        MarketInstructions::sell_buy_common(client, client_token, client_quote, settings, &data)
+    }
+
+    pub fn buy_exact_lamports(
+        client: &Pubkey,
+        client_token: &Pubkey,
+        client_quote: Option<&Pubkey>,
+        settings: &MarketSettings,
+        lamports: u64,
+        min_tokens: u64,
+    ) -> Instruction {
+        let data = MarketInstructions::BuyExactLamports {
+            lamports,
+            min_tokens,
+        };
+        MarketInstructions::sell_buy_common(client, client_token, client_quote, settings, &data)
+    }
+
+    pub fn buy_with_signed_quote(
+        client: &Pubkey,
+        client_token: &Pubkey,
+        client_quote: Option<&Pubkey>,
+        settings: &MarketSettings,
+        tokens: u64,
+        quote: SignedQuote,
+    ) -> Instruction {
+        let data = MarketInstructions::BuyWithSignedQuote { tokens, quote };
+        let nonce_pubkey = MarketSettings::nonce_pubkey_with_bump(&settings.mint, quote.nonce).0;
+        let mut instruction = MarketInstructions::sell_buy_common(
+            client,
+            client_token,
+            client_quote,
+            settings,
+            &data,
+        );
+        instruction
+            .accounts
+            .push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+        instruction
+            .accounts
+            .push(AccountMeta::new(nonce_pubkey, false));
+        instruction
+    }
+
+    pub fn buy_with_proof(
+        client: &Pubkey,
+        client_token: &Pubkey,
+        client_quote: Option<&Pubkey>,
+        settings: &MarketSettings,
+        tokens: u64,
+        allocation: Option<u64>,
+        proof: Vec<[u8; 32]>,
+    ) -> Instruction {
+        let data = MarketInstructions::BuyWithProof {
+            tokens,
+            allocation,
+            proof,
+        };
+        let mut instruction = MarketInstructions::sell_buy_common(
+            client,
+            client_token,
+            client_quote,
+            settings,
+            &data,
+        );
+        // The allocation is counted in the buyer record as well as the per-buyer limit
+        if allocation.is_some() && settings.limits.max_tokens_per_buyer.is_none() {
+            let buyer_record_pubkey =
+                MarketSettings::buyer_record_pubkey_with_bump(&settings.mint, client).0;
+            instruction
+                .accounts
+                .push(AccountMeta::new(buyer_record_pubkey, false));
+        }
+        instruction
+    }
+
+    pub fn withdraw_lamports(
+        treasury_authority: &Pubkey,
+        mint: &Pubkey,
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        let market_settings_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+        let market_lamports_pubkey = MarketSettings::lamports_account_pubkey(mint).0;
+        let market_token_pubkey = MarketSettings::token_pubkey_with_bump(mint).0;
+        let quote_vault_pubkey = MarketSettings::quote_vault_pubkey_with_bump(mint).0;
         let token_program = spl_token::id();
         let system_program = system_program::id();
 
         Instruction::new_with_borsh(
             id(),
-            data,
+            &MarketInstructions::WithdrawLamports { amount },
             vec![
-                AccountMeta::new(*client, true),
-                AccountMeta::new(*client_token, false),
-                AccountMeta::new(market_lamports_pubkey, false),
+                AccountMeta::new_readonly(*treasury_authority, true),
                 AccountMeta::new_readonly(market_settings_pubkey, false),
+                AccountMeta::new(market_lamports_pubkey, false),
+                AccountMeta::new(*destination, false),
+                AccountMeta::new_readonly(market_token_pubkey, false),
+                AccountMeta::new_readonly(*mint, false),
+                AccountMeta::new_readonly(system_program, false),
+                AccountMeta::new(quote_vault_pubkey, false),
+                AccountMeta::new_readonly(token_program, false),
+            ],
+        )
+    }
+
+    pub fn withdraw_tokens(
+        treasury_authority: &Pubkey,
+        mint: &Pubkey,
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        let market_settings_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+        let market_token_pubkey = MarketSettings::token_pubkey_with_bump(mint).0;
+        let token_program = spl_token::id();
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::WithdrawTokens {
+                amount,
+                destination: *destination,
+            },
+            vec![
+                AccountMeta::new_readonly(*treasury_authority, true),
+                AccountMeta::new(market_settings_pubkey, false),
                 AccountMeta::new(market_token_pubkey, false),
+                AccountMeta::new(*destination, false),
                 AccountMeta::new_readonly(token_program, false),
-                AccountMeta::new_readonly(system_program, false),
             ],
         )
     }
 
-    pub fn sell(client: &Pubkey, client_token: &Pubkey, args: TokensNumber) -> Instruction {
-        let data = MarketInstructions::Sell(args);
-        MarketInstructions::sell_buy_common(client, client_token, &data)
+    pub fn deposit_tokens(
+        treasury_authority: &Pubkey,
+        mint: &Pubkey,
+        treasury_token: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        let market_settings_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+        let market_token_pubkey = MarketSettings::token_pubkey_with_bump(mint).0;
+        let token_program = spl_token::id();
+
+        Instruction::new_with_borsh(
+            id(),
+            &MarketInstructions::DepositTokens { amount },
+            vec![
+                AccountMeta::new_readonly(*treasury_authority, true),
+                AccountMeta::new(market_settings_pubkey, false),
+                AccountMeta::new(*treasury_token, false),
+                AccountMeta::new(market_token_pubkey, false),
+                AccountMeta::new_readonly(token_program, false),
+            ],
+        )
     }
 
-    pub fn buy(client: &Pubkey, client_token: &Pubkey, args: TokensNumber) -> Instruction {
-        let data = MarketInstructions::Buy(args);
-        MarketInstructions::sell_buy_common(client, client_token, &data)
+    /// `quote_destination` receives the quote tokens, required if the market has a quote mint
+    pub fn close_market(
+        admin: &Pubkey,
+        mint: &Pubkey,
+        token_destination: &Pubkey,
+        lamports_destination: &Pubkey,
+        quote_destination: Option<&Pubkey>,
+    ) -> Instruction {
+        let market_settings_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+        let market_token_pubkey = MarketSettings::token_pubkey_with_bump(mint).0;
+        let market_lamports_pubkey = MarketSettings::lamports_account_pubkey(mint).0;
+        let token_program = spl_token::id();
+        let system_program = system_program::id();
+
+        let mut accounts = vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(market_settings_pubkey, false),
+            AccountMeta::new(market_token_pubkey, false),
+            AccountMeta::new(market_lamports_pubkey, false),
+            AccountMeta::new(*token_destination, false),
+            AccountMeta::new(*lamports_destination, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(system_program, false),
+        ];
+        if let Some(quote_destination) = quote_destination {
+            let quote_vault_pubkey = MarketSettings::quote_vault_pubkey_with_bump(mint).0;
+            accounts.push(AccountMeta::new(quote_vault_pubkey, false));
+            accounts.push(AccountMeta::new(*quote_destination, false));
+        }
+
+        Instruction::new_with_borsh(id(), &MarketInstructions::CloseMarket, accounts)
     }
 }
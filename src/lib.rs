@@ -1,6 +1,9 @@
+pub mod allowlist;
 pub mod error;
 pub mod instruction;
+pub mod oracle;
 pub mod processor;
+pub mod secp256k1;
 pub mod state;
 
 #[cfg(not(feature = "no-entrypoint"))]
@@ -0,0 +1,31 @@
+//! Price feed accounts read by the oracle pricing mode.
+//!
+//! The market doesn't own the feed, it trusts the account chosen by the admin.
+//! The feed's data starts with a borsh encoded [`OraclePrice`], trailing bytes are ignored:
+//!
+//! | Offset | Size | Field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 8    | `price`, little endian `u64`           |
+//! | 8      | 8    | `slot`, little endian `u64`            |
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::program_error::ProgramError;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub struct OraclePrice {
+    /// Price of `price_denominator` whole tokens, in the market's quote units
+    pub price: u64,
+    /// Slot the price was published at
+    pub slot: u64,
+}
+
+impl OraclePrice {
+    pub const LEN: usize = 8 + 8;
+
+    pub fn from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        let mut data = data
+            .get(..Self::LEN)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        Self::deserialize(&mut data).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
@@ -1,13 +1,60 @@
-use crate::instruction::MarketInstructions;
+use crate::{
+    error::MarketError,
+    instruction::{MarketInstructions, TokensNumber},
+};
+use admin_transfer::{
+    process_accept_admin, process_cancel_admin_transfer, process_propose_admin,
+    process_set_admin_multisig,
+};
+use block_wallet::{process_block_wallet, process_unblock_wallet};
 use borsh::BorshDeserialize;
 use buy_sell::{process_buy_sell, OperationType};
+use close_market::process_close_market;
 use initialize_store::process_initialize_store;
-use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
-use update_price::process_update_price;
+use inventory::{process_deposit_tokens, process_withdraw_tokens};
+use pause::process_set_paused;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey, system_program,
+};
+use update_allowlist::process_update_allowlist;
+use update_fee::process_update_fee;
+use update_price::{
+    process_cancel_price_update, process_update_price, process_update_price_band,
+    process_update_price_timelock,
+};
+use update_roles::{process_update_quote_signer, process_update_roles};
+use update_sale_limits::process_update_sale_limits;
+use withdraw_lamports::process_withdraw_lamports;
 
+pub mod admin_transfer;
+pub mod block_wallet;
 pub mod buy_sell;
+pub mod close_market;
 pub mod initialize_store;
+pub mod inventory;
+pub mod pause;
+pub mod update_allowlist;
+pub mod update_fee;
 pub mod update_price;
+pub mod update_roles;
+pub mod update_sale_limits;
+pub mod withdraw_lamports;
+
+pub fn check_token_program(token_program_info: &AccountInfo) -> ProgramResult {
+    if *token_program_info.key != spl_token::id() {
+        return Err(MarketError::TokenProgramMismatch.into());
+    }
+
+    Ok(())
+}
+
+pub fn check_system_program(system_program_info: &AccountInfo) -> ProgramResult {
+    if *system_program_info.key != system_program::id() {
+        return Err(MarketError::SystemProgramMismatch.into());
+    }
+
+    Ok(())
+}
 
 pub fn process_instruction(
     _program_id: &Pubkey,
@@ -18,7 +65,102 @@ pub fn process_instruction(
     match instruction {
         MarketInstructions::InitializeStore(args) => process_initialize_store(accounts, args),
         MarketInstructions::UpdatePrice(args) => process_update_price(accounts, args),
-        MarketInstructions::Buy(args) => process_buy_sell(accounts, args, OperationType::Buy),
-        MarketInstructions::Sell(args) => process_buy_sell(accounts, args, OperationType::Sell),
+        MarketInstructions::Buy(TokensNumber(tokens)) => {
+            let operation = OperationType::Buy {
+                tokens,
+                max_lamports: None,
+            };
+            process_buy_sell(accounts, operation)
+        }
+        MarketInstructions::Sell(TokensNumber(tokens)) => {
+            let operation = OperationType::Sell {
+                tokens,
+                min_lamports: None,
+            };
+            process_buy_sell(accounts, operation)
+        }
+        MarketInstructions::BuyWithLimit {
+            tokens,
+            max_lamports,
+        } => {
+            let operation = OperationType::Buy {
+                tokens,
+                max_lamports: Some(max_lamports),
+            };
+            process_buy_sell(accounts, operation)
+        }
+        MarketInstructions::SellWithLimit {
+            tokens,
+            min_lamports,
+        } => {
+            let operation = OperationType::Sell {
+                tokens,
+                min_lamports: Some(min_lamports),
+            };
+            process_buy_sell(accounts, operation)
+        }
+        MarketInstructions::BuyExactLamports {
+            lamports,
+            min_tokens,
+        } => {
+            let operation = OperationType::BuyExactLamports {
+                lamports,
+                min_tokens,
+            };
+            process_buy_sell(accounts, operation)
+        }
+        MarketInstructions::WithdrawLamports { amount } => {
+            process_withdraw_lamports(accounts, amount)
+        }
+        MarketInstructions::WithdrawTokens {
+            amount,
+            destination,
+        } => process_withdraw_tokens(accounts, amount, destination),
+        MarketInstructions::DepositTokens { amount } => process_deposit_tokens(accounts, amount),
+        MarketInstructions::CloseMarket => process_close_market(accounts),
+        MarketInstructions::UpdateFee(args) => process_update_fee(accounts, args),
+        MarketInstructions::Pause => process_set_paused(accounts, true),
+        MarketInstructions::Resume => process_set_paused(accounts, false),
+        MarketInstructions::ProposeAdmin { new_admin } => {
+            process_propose_admin(accounts, new_admin)
+        }
+        MarketInstructions::AcceptAdmin => process_accept_admin(accounts),
+        MarketInstructions::CancelAdminTransfer => process_cancel_admin_transfer(accounts),
+        MarketInstructions::BuyWithSignedQuote { tokens, quote } => {
+            let operation = OperationType::BuyWithSignedQuote { tokens, quote };
+            process_buy_sell(accounts, operation)
+        }
+        MarketInstructions::UpdateSaleLimits(limits) => {
+            process_update_sale_limits(accounts, limits)
+        }
+        MarketInstructions::UpdateAllowlist { root } => process_update_allowlist(accounts, root),
+        MarketInstructions::BuyWithProof {
+            tokens,
+            allocation,
+            proof,
+        } => {
+            let operation = OperationType::BuyWithProof {
+                tokens,
+                allocation,
+                proof,
+            };
+            process_buy_sell(accounts, operation)
+        }
+        MarketInstructions::BlockWallet { wallet } => process_block_wallet(accounts, wallet),
+        MarketInstructions::UnblockWallet { wallet } => process_unblock_wallet(accounts, wallet),
+        MarketInstructions::SetAdminMultisig(multisig) => {
+            process_set_admin_multisig(accounts, multisig)
+        }
+        MarketInstructions::UpdateRoles(roles) => process_update_roles(accounts, roles),
+        MarketInstructions::UpdatePriceTimelock { price_timelock } => {
+            process_update_price_timelock(accounts, price_timelock)
+        }
+        MarketInstructions::CancelPriceUpdate => process_cancel_price_update(accounts),
+        MarketInstructions::UpdatePriceBand(price_band) => {
+            process_update_price_band(accounts, price_band)
+        }
+        MarketInstructions::UpdateQuoteSigner { quote_signer } => {
+            process_update_quote_signer(accounts, quote_signer)
+        }
     }
 }
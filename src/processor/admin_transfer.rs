@@ -0,0 +1,114 @@
+use crate::{
+    error::MarketError,
+    state::{AdminMultisig, MarketSettings},
+};
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+pub fn process_propose_admin(accounts: &[AccountInfo], new_admin: Pubkey) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    market_settings.pending_admin = Some(new_admin);
+
+    msg!("Proposing {} as the admin", new_admin);
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
+
+pub fn process_accept_admin(accounts: &[AccountInfo]) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let new_admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    if !new_admin_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+    if market_settings.pending_admin != Some(*new_admin_info.key) {
+        return Err(MarketError::PendingAdminMismatch.into());
+    }
+
+    // Roles the old admin kept move to the new one
+    if market_settings.price_authority == market_settings.admin {
+        market_settings.price_authority = *new_admin_info.key;
+    }
+    if market_settings.treasury_authority == market_settings.admin {
+        market_settings.treasury_authority = *new_admin_info.key;
+    }
+
+    // The old admin's quote key can't sign for the new one
+    market_settings.quote_signer = None;
+
+    // The new admin administers the market alone until they set up a multisig
+    market_settings.admin = *new_admin_info.key;
+    market_settings.pending_admin = None;
+    market_settings.admin_multisig = None;
+
+    msg!("{} is the admin now", new_admin_info.key);
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
+
+pub fn process_cancel_admin_transfer(accounts: &[AccountInfo]) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    market_settings.pending_admin = None;
+
+    msg!("Cancelling the admin transfer");
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
+
+pub fn process_set_admin_multisig(
+    accounts: &[AccountInfo],
+    multisig: Option<AdminMultisig>,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    if matches!(&multisig, Some(multisig) if !multisig.is_valid()) {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    match &multisig {
+        Some(multisig) => msg!(
+            "Setting a {} of {} admin multisig",
+            multisig.threshold,
+            multisig.signers.len()
+        ),
+        None => msg!("Removing the admin multisig"),
+    }
+    // Roles the admin keeps follow the multisig, as they are checked as the admin
+    market_settings.admin_multisig = multisig;
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
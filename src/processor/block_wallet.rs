@@ -0,0 +1,99 @@
+use crate::{
+    error::MarketError,
+    id,
+    processor::check_system_program,
+    state::{MarketSettings, BLOCKED_SEED},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+    sysvar::Sysvar,
+};
+
+/// Bump of the wallet's block record, checking that `block_record_info` is the record
+fn block_record_bump(
+    market_settings: &MarketSettings,
+    block_record_info: &AccountInfo,
+    wallet: &Pubkey,
+) -> Result<u8, ProgramError> {
+    let (block_record_pubkey, bump) =
+        MarketSettings::block_record_pubkey_with_bump(&market_settings.mint, wallet);
+    if *block_record_info.key != block_record_pubkey {
+        return Err(MarketError::BlockRecordPubkeyMismatch.into());
+    }
+
+    Ok(bump)
+}
+
+pub fn process_block_wallet(accounts: &[AccountInfo], wallet: Pubkey) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+    let block_record_info = next_account_info(account_iter)?;
+    let system_program_info = next_account_info(account_iter)?;
+
+    check_system_program(system_program_info)?;
+
+    let market_settings = MarketSettings::load(market_info)?;
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+    let bump = block_record_bump(&market_settings, block_record_info, &wallet)?;
+    if MarketSettings::is_blocked(block_record_info) {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let mint = &market_settings.mint;
+    let seed: &[&[_]] = &[
+        BLOCKED_SEED.as_bytes(),
+        mint.as_ref(),
+        wallet.as_ref(),
+        &[bump],
+    ];
+    // Lamports sent to the record beforehand must not block its creation
+    let rent = Rent::get()?.minimum_balance(0);
+    let top_up = rent.saturating_sub(block_record_info.lamports());
+
+    msg!("Block {}", wallet);
+    if top_up > 0 {
+        invoke(
+            &system_instruction::transfer(admin_info.key, block_record_info.key, top_up),
+            &[admin_info.clone(), block_record_info.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::assign(block_record_info.key, &id()),
+        std::slice::from_ref(block_record_info),
+        &[seed],
+    )
+}
+
+pub fn process_unblock_wallet(accounts: &[AccountInfo], wallet: Pubkey) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+    let block_record_info = next_account_info(account_iter)?;
+
+    let market_settings = MarketSettings::load(market_info)?;
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+    block_record_bump(&market_settings, block_record_info, &wallet)?;
+    if !MarketSettings::is_blocked(block_record_info) {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    msg!("Unblock {}", wallet);
+    let admin_lamports = admin_info
+        .lamports()
+        .checked_add(block_record_info.lamports())
+        .ok_or(MarketError::TooManyLamports)?;
+    **admin_info.lamports.borrow_mut() = admin_lamports;
+    **block_record_info.lamports.borrow_mut() = 0;
+
+    Ok(())
+}
@@ -1,48 +1,525 @@
 use crate::{
+    allowlist::{self, AllowlistEntry},
     error::MarketError,
-    instruction::TokensNumber,
-    state::{MarketSettings, LAMPORTS_SEED, SETTINGS_SEED},
+    id,
+    instruction::SignedQuote,
+    oracle::OraclePrice,
+    processor::{check_system_program, check_token_program},
+    secp256k1,
+    state::{
+        BuyerRecord, MarketSettings, PricingMode, BUYER_SEED, LAMPORTS_SEED, MAX_FEE_BASIS_POINTS,
+        NONCE_SEED, SETTINGS_SEED,
+    },
 };
-use borsh::BorshDeserialize;
+use borsh::BorshSerialize;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    clock::Clock,
     entrypoint::ProgramResult,
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
     program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+    sysvar::{self, Sysvar},
 };
+use std::convert::{TryFrom, TryInto};
 
 pub enum OperationType {
-    Buy,
-    Sell,
+    /// Fails if the client would pay more than `max_lamports`
+    Buy {
+        tokens: u64,
+        max_lamports: Option<u64>,
+    },
+    /// Buys as many tokens as `lamports` can pay for including the fee,
+    /// the change stays with the client. Fails if the client would get less than `min_tokens`
+    BuyExactLamports { lamports: u64, min_tokens: u64 },
+    /// Fails if the client would receive less than `min_lamports`
+    Sell {
+        tokens: u64,
+        min_lamports: Option<u64>,
+    },
+    /// Buys for the price of a quote signed with the admin's quote key
+    BuyWithSignedQuote { tokens: u64, quote: SignedQuote },
+    /// Buys as a wallet of the market's allowlist, at most `allocation` tokens in total
+    BuyWithProof {
+        tokens: u64,
+        allocation: Option<u64>,
+        proof: Vec<[u8; 32]>,
+    },
 }
 
-fn process_buy<'info>(
+struct TradeAccounts<'a, 'info> {
+    client_info: &'a AccountInfo<'info>,
+    client_token_info: &'a AccountInfo<'info>,
+    market_settings_info: &'a AccountInfo<'info>,
+    market_token_info: &'a AccountInfo<'info>,
+    fee_recipient_info: &'a AccountInfo<'info>,
+    quote: QuoteAccounts<'a, 'info>,
+    /// Only with a per-buyer limit
+    buyer_record_info: Option<&'a AccountInfo<'info>>,
+}
+
+/// Accounts the price is paid from and to: system accounts for lamports
+/// or token accounts if the market has a quote mint
+struct QuoteAccounts<'a, 'info> {
+    client_info: &'a AccountInfo<'info>,
+    market_info: &'a AccountInfo<'info>,
+    is_token: bool,
+}
+
+impl QuoteAccounts<'_, '_> {
+    fn unit(&self) -> &'static str {
+        if self.is_token {
+            "quote tokens"
+        } else {
+            "lamports"
+        }
+    }
+
+    fn balance(&self, info: &AccountInfo) -> Result<u64, ProgramError> {
+        if self.is_token {
+            Ok(spl_token::state::Account::unpack(&info.data.borrow())?.amount)
+        } else {
+            Ok(info.lamports())
+        }
+    }
+
+    fn transfer<'info>(
+        &self,
+        source_info: &AccountInfo<'info>,
+        destination_info: &AccountInfo<'info>,
+        authority_info: &AccountInfo<'info>,
+        amount: u64,
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        if self.is_token {
+            let ix = spl_token::instruction::transfer(
+                &spl_token::id(),
+                source_info.key,
+                destination_info.key,
+                authority_info.key,
+                &[authority_info.key],
+                amount,
+            )?;
+            invoke_signed(
+                &ix,
+                &[
+                    source_info.clone(),
+                    destination_info.clone(),
+                    authority_info.clone(),
+                ],
+                signers_seeds,
+            )
+        } else {
+            let ix = system_instruction::transfer(source_info.key, destination_info.key, amount);
+            invoke_signed(
+                &ix,
+                &[source_info.clone(), destination_info.clone()],
+                signers_seeds,
+            )
+        }
+    }
+}
+
+/// Quote and token reserves of a constant product market
+struct Reserves {
+    quote: u64,
+    tokens: u64,
+}
+
+impl Reserves {
+    /// Price of taking `tokens` out of the reserves, rounded up
+    fn buy_cost(&self, tokens: u64) -> Option<u64> {
+        let remaining = u128::from(self.tokens.checked_sub(tokens)?);
+        u128::from(self.quote)
+            .checked_mul(tokens.into())?
+            .checked_add(remaining.checked_sub(1)?)?
+            .checked_div(remaining)?
+            .try_into()
+            .ok()
+    }
+
+    /// Price of putting `tokens` into the reserves, rounded down
+    fn sell_proceeds(&self, tokens: u64) -> Option<u64> {
+        u128::from(self.quote)
+            .checked_mul(tokens.into())?
+            .checked_div(u128::from(self.tokens).checked_add(tokens.into())?)?
+            .try_into()
+            .ok()
+    }
+
+    /// The largest number of tokens whose buy cost doesn't exceed `lamports`
+    fn tokens_for_cost(&self, lamports: u64) -> Option<u64> {
+        u128::from(self.tokens)
+            .checked_mul(lamports.into())?
+            .checked_div(u128::from(self.quote).checked_add(lamports.into())?)?
+            .try_into()
+            .ok()
+    }
+}
+
+/// Sets the buy and sell prices `spread_basis_points` above and below the oracle price,
+/// rounded in the market's favor
+fn apply_oracle_price(
+    market_settings: &mut MarketSettings,
+    oracle_info: &AccountInfo,
+    spread_basis_points: u16,
+    max_staleness_slots: u64,
+) -> ProgramResult {
+    let oracle_price = OraclePrice::from_account_data(&oracle_info.data.borrow())?;
+
+    if Clock::get()?.slot.saturating_sub(oracle_price.slot) > max_staleness_slots {
+        return Err(MarketError::StaleOraclePrice.into());
+    }
+
+    // A zero price would give the tokens away
+    if oracle_price.price == 0 {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let max_spread = u128::from(MAX_FEE_BASIS_POINTS);
+    let spread = u128::from(spread_basis_points);
+    let price = u128::from(oracle_price.price);
+
+    // The buy price is rounded up, so the spread is never narrower than configured
+    market_settings.buy_price = (price * (max_spread + spread))
+        .checked_add(max_spread - 1)
+        .map(|numerator| numerator / max_spread)
+        .and_then(|buy_price| buy_price.try_into().ok())
+        .ok_or(MarketError::TooManyLamports)?;
+    market_settings.sell_price = (price * max_spread.saturating_sub(spread) / max_spread)
+        .try_into()
+        .map_err(|_| MarketError::TooManyLamports)?;
+
+    Ok(())
+}
+
+/// Buy price of the auction at `now`, rounded up
+fn auction_price(
+    start_price: u64,
+    end_price: u64,
+    start_time: i64,
+    end_time: i64,
+    now: i64,
+) -> u64 {
+    if now <= start_time {
+        return start_price;
+    }
+    if now >= end_time {
+        return end_price;
+    }
+
+    // The parameters are validated on initialization, so nothing overflows
+    let drop = u128::from(start_price - end_price);
+    let elapsed = (i128::from(now) - i128::from(start_time)) as u128;
+    let duration = (i128::from(end_time) - i128::from(start_time)) as u128;
+
+    start_price - (drop * elapsed / duration) as u64
+}
+
+/// Checks that the instruction before the current one verified the quote key's signature
+/// of `quote` for the client
+fn verify_signed_quote(
+    market_settings: &MarketSettings,
+    accounts: &TradeAccounts,
+    instructions_info: &AccountInfo,
+    quote: &SignedQuote,
+) -> ProgramResult {
+    if *instructions_info.key != sysvar::instructions::id() {
+        return Err(MarketError::InstructionsSysvarMismatch.into());
+    }
+
+    let data = instructions_info.data.borrow();
+    let previous_index = sysvar::instructions::load_current_index(&data)
+        .checked_sub(1)
+        .ok_or(MarketError::InvalidQuoteSignature)?;
+    let instruction = sysvar::instructions::load_instruction_at(previous_index.into(), &data)
+        .map_err(|_| MarketError::InvalidQuoteSignature)?;
+    // The verification instruction refers to itself by its position in the transaction
+    let previous_index =
+        u8::try_from(previous_index).map_err(|_| MarketError::InvalidQuoteSignature)?;
+    let expected_message =
+        quote.message(accounts.market_settings_info.key, accounts.client_info.key);
+
+    match secp256k1::signed_message(&instruction, previous_index) {
+        Some((signer, message))
+            if Some(signer) == market_settings.quote_signer && message == expected_message =>
+        {
+            Ok(())
+        }
+        _ => Err(MarketError::InvalidQuoteSignature.into()),
+    }
+}
+
+/// Creates the record of the quote's nonce, so the quote can't be used again
+fn use_quote_nonce<'a>(
+    client_info: &AccountInfo<'a>,
+    nonce_info: &AccountInfo<'a>,
+    mint: &Pubkey,
+    nonce: u64,
+) -> ProgramResult {
+    let (nonce_pubkey, bump) = MarketSettings::nonce_pubkey_with_bump(mint, nonce);
+    if nonce_pubkey != *nonce_info.key {
+        return Err(MarketError::NoncePubkeyMismatch.into());
+    }
+
+    if *nonce_info.owner == id() {
+        return Err(MarketError::QuoteNonceUsed.into());
+    }
+
+    let nonce_bytes = nonce.to_le_bytes();
+    let seed: &[&[_]] = &[NONCE_SEED.as_bytes(), mint.as_ref(), &nonce_bytes, &[bump]];
+    // Lamports sent to the record beforehand must not use the quote up
+    let rent = Rent::get()?.minimum_balance(0);
+    let top_up = rent.saturating_sub(nonce_info.lamports());
+
+    msg!("Record the quote's nonce");
+    if top_up > 0 {
+        invoke(
+            &system_instruction::transfer(client_info.key, nonce_info.key, top_up),
+            &[client_info.clone(), nonce_info.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::assign(nonce_info.key, &id()),
+        std::slice::from_ref(nonce_info),
+        &[seed],
+    )
+}
+
+/// Price and fee of buying `tokens`
+fn buy_cost(
+    market_settings: &MarketSettings,
+    reserves: Option<&Reserves>,
+    tokens: u64,
+) -> Result<(u64, u64), ProgramError> {
+    let lamports = match reserves {
+        Some(reserves) => {
+            // The last token would cost infinitely much
+            if tokens >= reserves.tokens {
+                return Err(MarketError::InsufficientLiquidity.into());
+            }
+            reserves.buy_cost(tokens)
+        }
+        None => market_settings.buy_cost(tokens),
+    }
+    .ok_or(MarketError::TooManyLamports)?;
+    let fee = market_settings
+        .fee(lamports)
+        .ok_or(MarketError::TooManyLamports)?;
+
+    Ok((lamports, fee))
+}
+
+/// Price of selling `tokens`, the fee is taken out of it
+fn sell_proceeds(
+    market_settings: &MarketSettings,
+    reserves: Option<&Reserves>,
+    tokens: u64,
+) -> Result<u64, ProgramError> {
+    match reserves {
+        Some(reserves) => reserves.sell_proceeds(tokens),
+        None => market_settings.sell_proceeds(tokens),
+    }
+    .ok_or_else(|| MarketError::TooManyLamports.into())
+}
+
+/// The largest number of tokens whose price and fee fit into `lamports`
+fn tokens_for_lamports(
+    market_settings: &MarketSettings,
+    reserves: Option<&Reserves>,
+    market_tokens: u64,
+    lamports: u64,
+) -> Result<u64, ProgramError> {
+    if market_settings.pricing.is_flat() && market_settings.buy_price == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // The largest price which fits into `lamports` with its fee
+    let max_fee = u128::from(MAX_FEE_BASIS_POINTS);
+    let total_basis_points = max_fee + u128::from(market_settings.fee_basis_points);
+    let mut price: u64 = (u128::from(lamports) * max_fee / total_basis_points)
+        .try_into()
+        .map_err(|_| MarketError::TooManyLamports)?;
+
+    // The fee is rounded up, so the estimation can exceed the budget by a lamport
+    loop {
+        let fee = market_settings
+            .fee(price)
+            .ok_or(MarketError::TooManyLamports)?;
+        match price.checked_add(fee) {
+            Some(total) if total <= lamports => break,
+            _ => price -= 1,
+        }
+    }
+
+    match reserves {
+        Some(reserves) => reserves.tokens_for_cost(price),
+        None => market_settings.tokens_for_cost(price, market_tokens),
+    }
+    .ok_or_else(|| MarketError::TooManyLamports.into())
+}
+
+/// Checks the sale schedule and caps for buying `tokens`,
+/// `allocation` is the client's own cap from the allowlist
+fn check_sale_limits(
+    tokens: u64,
+    allocation: Option<u64>,
+    market_settings: &MarketSettings,
+    accounts: &TradeAccounts,
+) -> ProgramResult {
+    let limits = market_settings.limits;
+
+    if limits.opens_at.is_some() || limits.closes_at.is_some() {
+        let now = Clock::get()?.unix_timestamp;
+
+        if matches!(limits.opens_at, Some(opens_at) if now < opens_at) {
+            return Err(MarketError::SaleNotOpen.into());
+        }
+
+        if matches!(limits.closes_at, Some(closes_at) if now >= closes_at) {
+            return Err(MarketError::SaleClosed.into());
+        }
+    }
+
+    // Tokens bought back don't count, so the cap can't be reopened
+    let tokens_sold = market_settings
+        .tokens_sold_total
+        .checked_add(tokens)
+        .ok_or(ProgramError::InvalidArgument)?;
+    if matches!(limits.max_tokens_sold, Some(max_tokens_sold) if tokens_sold > max_tokens_sold) {
+        return Err(MarketError::SaleCapExceeded.into());
+    }
+
+    let max_tokens_per_buyer = match (limits.max_tokens_per_buyer, allocation) {
+        (Some(max_tokens_per_buyer), Some(allocation)) => {
+            Some(max_tokens_per_buyer.min(allocation))
+        }
+        (max_tokens_per_buyer, allocation) => max_tokens_per_buyer.or(allocation),
+    };
+    if let Some(max_tokens_per_buyer) = max_tokens_per_buyer {
+        let buyer_record_info = accounts
+            .buyer_record_info
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        record_purchase(
+            tokens,
+            max_tokens_per_buyer,
+            &market_settings.mint,
+            accounts.client_info,
+            buyer_record_info,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Counts `tokens` in the client's buyer record, which is created on the first purchase
+fn record_purchase<'a>(
+    tokens: u64,
+    max_tokens_per_buyer: u64,
+    mint: &Pubkey,
+    client_info: &AccountInfo<'a>,
+    buyer_record_info: &AccountInfo<'a>,
+) -> ProgramResult {
+    let (buyer_record_pubkey, bump) =
+        MarketSettings::buyer_record_pubkey_with_bump(mint, client_info.key);
+    if buyer_record_pubkey != *buyer_record_info.key {
+        return Err(MarketError::BuyerRecordPubkeyMismatch.into());
+    }
+
+    if buyer_record_info.data_is_empty() {
+        let seed: &[&[_]] = &[
+            BUYER_SEED.as_bytes(),
+            mint.as_ref(),
+            client_info.key.as_ref(),
+            &[bump],
+        ];
+        // Lamports sent to the record beforehand must not block its creation
+        let rent = Rent::get()?.minimum_balance(BuyerRecord::LEN);
+        let top_up = rent.saturating_sub(buyer_record_info.lamports());
+
+        msg!("Create the buyer record");
+        if top_up > 0 {
+            invoke(
+                &system_instruction::transfer(client_info.key, buyer_record_info.key, top_up),
+                &[client_info.clone(), buyer_record_info.clone()],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(buyer_record_info.key, BuyerRecord::LEN as u64),
+            std::slice::from_ref(buyer_record_info),
+            &[seed],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(buyer_record_info.key, &id()),
+            std::slice::from_ref(buyer_record_info),
+            &[seed],
+        )?;
+    }
+
+    let mut buyer_record = BuyerRecord::from_account_data(&buyer_record_info.data.borrow())?;
+    buyer_record.tokens_bought = buyer_record
+        .tokens_bought
+        .checked_add(tokens)
+        .ok_or(ProgramError::InvalidArgument)?;
+
+    if buyer_record.tokens_bought > max_tokens_per_buyer {
+        return Err(MarketError::BuyerCapExceeded.into());
+    }
+
+    buyer_record.serialize(&mut *buyer_record_info.data.borrow_mut())?;
+
+    Ok(())
+}
+
+fn process_buy(
     tokens_number: u64,
-    buy_price: u64,
-    market_settings_info: &AccountInfo<'info>,
-    market_token_info: &AccountInfo<'info>,
-    market_lamports_info: &AccountInfo<'info>,
-    client_info: &AccountInfo<'info>,
-    client_token_info: &AccountInfo<'info>,
+    lamports: u64,
+    fee: u64,
+    allocation: Option<u64>,
+    market_settings: &mut MarketSettings,
+    accounts: &TradeAccounts,
 ) -> ProgramResult {
-    let lamports = buy_price
-        .checked_mul(tokens_number)
+    let TradeAccounts {
+        client_info,
+        client_token_info,
+        market_settings_info,
+        market_token_info,
+        fee_recipient_info,
+        ref quote,
+        ..
+    } = *accounts;
+
+    check_sale_limits(tokens_number, allocation, market_settings, accounts)?;
+
+    let total_lamports = lamports
+        .checked_add(fee)
         .ok_or(MarketError::TooManyLamports)?;
 
-    if client_info.lamports() < lamports {
+    if quote.balance(quote.client_info)? < total_lamports {
         return Err(ProgramError::InsufficientFunds);
     }
 
-    let ix = solana_program::system_instruction::transfer(
-        client_info.key,
-        market_lamports_info.key,
+    msg!("Transfer {} {} to the market", lamports, quote.unit());
+    quote.transfer(
+        quote.client_info,
+        quote.market_info,
+        client_info,
         lamports,
-    );
+        &[],
+    )?;
 
-    msg!("Transfer {} lamports to the market", lamports);
-    invoke(&ix, &[client_info.clone(), market_lamports_info.clone()])?;
+    if fee > 0 {
+        msg!(
+            "Transfer {} {} of fee to the fee recipient",
+            fee,
+            quote.unit()
+        );
+        quote.transfer(quote.client_info, fee_recipient_info, client_info, fee, &[])?;
+    }
 
     let ix = spl_token::instruction::transfer(
         &spl_token::id(),
@@ -53,8 +530,9 @@ fn process_buy<'info>(
         tokens_number,
     )?;
 
-    let bump = MarketSettings::settings_pubkey_with_bump().1;
-    let seed: &[&[_]] = &[SETTINGS_SEED.as_bytes(), &[bump]];
+    let mint = &market_settings.mint;
+    let bump = MarketSettings::settings_pubkey_with_bump(mint).1;
+    let seed: &[&[_]] = &[SETTINGS_SEED.as_bytes(), mint.as_ref(), &[bump]];
 
     msg!("Transfer {} tokens to the client", tokens_number);
     invoke_signed(
@@ -67,40 +545,80 @@ fn process_buy<'info>(
         &[seed],
     )?;
 
+    market_settings.tokens_sold = market_settings
+        .tokens_sold
+        .checked_add(tokens_number)
+        .ok_or(ProgramError::InvalidArgument)?;
+    market_settings.tokens_sold_total = market_settings
+        .tokens_sold_total
+        .checked_add(tokens_number)
+        .ok_or(ProgramError::InvalidArgument)?;
+    market_settings.serialize(&mut *market_settings_info.data.borrow_mut())?;
+
     Ok(())
 }
 
-fn process_sell<'info>(
+fn process_sell(
     tokens_number: u64,
-    sell_price: u64,
-    market_token_info: &AccountInfo<'info>,
-    market_lamports_info: &AccountInfo<'info>,
-    client_info: &AccountInfo<'info>,
-    client_token_info: &AccountInfo<'info>,
+    lamports: u64,
+    fee: u64,
+    market_settings: &mut MarketSettings,
+    accounts: &TradeAccounts,
 ) -> ProgramResult {
-    let lamports = sell_price
-        .checked_mul(tokens_number)
-        .ok_or(MarketError::TooManyLamports)?;
+    let TradeAccounts {
+        client_info,
+        client_token_info,
+        market_settings_info,
+        market_token_info,
+        fee_recipient_info,
+        ref quote,
+        ..
+    } = *accounts;
 
-    if market_lamports_info.lamports() < lamports {
+    if quote.balance(quote.market_info)? < lamports {
         return Err(ProgramError::InsufficientFunds);
     }
 
-    let bump = MarketSettings::lamports_account_pubkey().1;
-    let seed: &[&[_]] = &[LAMPORTS_SEED.as_bytes(), &[bump]];
-    let ix = solana_program::system_instruction::transfer(
-        market_lamports_info.key,
-        client_info.key,
-        lamports,
-    );
+    // The vault is owned by the settings PDA, lamports are held by their own PDA
+    let mint = &market_settings.mint;
+    let (authority_info, seed, bump) = if quote.is_token {
+        let bump = MarketSettings::settings_pubkey_with_bump(mint).1;
+        (market_settings_info, SETTINGS_SEED, bump)
+    } else {
+        let bump = MarketSettings::lamports_account_pubkey(mint).1;
+        (quote.market_info, LAMPORTS_SEED, bump)
+    };
+    let seed: &[&[_]] = &[seed.as_bytes(), mint.as_ref(), &[bump]];
+    let client_lamports = lamports - fee;
 
-    msg!("Transfer {} lamports to the client", lamports);
-    invoke_signed(
-        &ix,
-        &[client_info.clone(), market_lamports_info.clone()],
+    msg!(
+        "Transfer {} {} to the client",
+        client_lamports,
+        quote.unit()
+    );
+    quote.transfer(
+        quote.market_info,
+        quote.client_info,
+        authority_info,
+        client_lamports,
         &[seed],
     )?;
 
+    if fee > 0 {
+        msg!(
+            "Transfer {} {} of fee to the fee recipient",
+            fee,
+            quote.unit()
+        );
+        quote.transfer(
+            quote.market_info,
+            fee_recipient_info,
+            authority_info,
+            fee,
+            &[seed],
+        )?;
+    }
+
     let ix = spl_token::instruction::transfer(
         &spl_token::id(),
         client_token_info.key,
@@ -120,14 +638,14 @@ fn process_sell<'info>(
         ],
     )?;
 
+    // With fixed prices the market may buy tokens it didn't sell
+    market_settings.tokens_sold = market_settings.tokens_sold.saturating_sub(tokens_number);
+    market_settings.serialize(&mut *market_settings_info.data.borrow_mut())?;
+
     Ok(())
 }
 
-pub fn process_buy_sell(
-    accounts: &[AccountInfo],
-    tokens_number: TokensNumber,
-    operation: OperationType,
-) -> ProgramResult {
+pub fn process_buy_sell(accounts: &[AccountInfo], operation: OperationType) -> ProgramResult {
     let account_iter = &mut accounts.iter();
 
     let client_info = next_account_info(account_iter)?;
@@ -135,22 +653,24 @@ pub fn process_buy_sell(
     let market_lamports_info = next_account_info(account_iter)?;
     let market_settings_info = next_account_info(account_iter)?;
     let market_token_info = next_account_info(account_iter)?;
+    let token_program_info = next_account_info(account_iter)?;
+    let system_program_info = next_account_info(account_iter)?;
+    let fee_recipient_info = next_account_info(account_iter)?;
+    let block_record_info = next_account_info(account_iter)?;
 
-    let market_settings = MarketSettings::try_from_slice(&market_settings_info.data.borrow())?;
+    check_token_program(token_program_info)?;
+    check_system_program(system_program_info)?;
+
+    let mut market_settings = MarketSettings::load(market_settings_info)?;
     let market_token_account = spl_token::state::Account::unpack(&market_token_info.data.borrow())?;
     let client_token_account = spl_token::state::Account::unpack(&client_token_info.data.borrow())?;
-    let expected_settings_pubkey = MarketSettings::settings_pubkey_with_bump().0;
-    let expected_token_pubkey = MarketSettings::token_pubkey_with_bump().0;
-    let tokens_number = tokens_number.0;
+    let expected_token_pubkey = MarketSettings::token_pubkey_with_bump(&market_settings.mint).0;
+    let expected_lamports_pubkey = MarketSettings::lamports_account_pubkey(&market_settings.mint).0;
 
     if !client_info.is_signer {
         return Err(ProgramError::MissingRequiredSignature);
     }
 
-    if tokens_number == 0 {
-        return Err(ProgramError::InvalidArgument);
-    }
-
     if client_token_account.owner != *client_info.key {
         return Err(ProgramError::InvalidArgument);
     }
@@ -163,31 +683,277 @@ pub fn process_buy_sell(
         return Err(MarketError::UnsupportedMint.into());
     }
 
-    if expected_settings_pubkey != *market_settings_info.key {
-        return Err(MarketError::SettingsPubkeyMismatch.into());
+    if market_settings.paused {
+        return Err(MarketError::MarketPaused.into());
     }
 
     if expected_token_pubkey != *market_token_info.key {
         return Err(MarketError::TokenPubkeyMismatch.into());
     }
 
-    match operation {
-        OperationType::Buy => process_buy(
-            tokens_number,
-            market_settings.buy_price,
-            market_settings_info,
-            market_token_info,
-            market_lamports_info,
-            client_info,
-            client_token_info,
-        ),
-        OperationType::Sell => process_sell(
-            tokens_number,
-            market_settings.sell_price,
-            market_token_info,
-            market_lamports_info,
+    // Checked even if the market has a quote mint and lamports aren't transferred
+    if expected_lamports_pubkey != *market_lamports_info.key {
+        return Err(MarketError::LamportsPubkeyMismatch.into());
+    }
+
+    if market_settings.fee_recipient != *fee_recipient_info.key {
+        return Err(MarketError::FeeRecipientMismatch.into());
+    }
+
+    let expected_block_record_pubkey =
+        MarketSettings::block_record_pubkey_with_bump(&market_settings.mint, client_info.key).0;
+    if expected_block_record_pubkey != *block_record_info.key {
+        return Err(MarketError::BlockRecordPubkeyMismatch.into());
+    }
+
+    if MarketSettings::is_blocked(block_record_info) {
+        return Err(MarketError::WalletBlocked.into());
+    }
+
+    let quote = if market_settings.quote_mint.is_some() {
+        let client_quote_info = next_account_info(account_iter)?;
+        let market_quote_info = next_account_info(account_iter)?;
+        let expected_quote_pubkey =
+            MarketSettings::quote_vault_pubkey_with_bump(&market_settings.mint).0;
+
+        if expected_quote_pubkey != *market_quote_info.key {
+            return Err(MarketError::QuoteVaultPubkeyMismatch.into());
+        }
+
+        QuoteAccounts {
+            client_info: client_quote_info,
+            market_info: market_quote_info,
+            is_token: true,
+        }
+    } else {
+        QuoteAccounts {
             client_info,
-            client_token_info,
-        ),
+            market_info: market_lamports_info,
+            is_token: false,
+        }
+    };
+
+    // Prices of a timelocked update are stored once they take effect
+    market_settings.apply_pending_price(Clock::get()?.unix_timestamp);
+
+    // The oracle prices are stored with the settings as the last prices traded at
+    if let PricingMode::Oracle {
+        oracle,
+        spread_basis_points,
+        max_staleness_slots,
+    } = market_settings.pricing
+    {
+        let oracle_info = next_account_info(account_iter)?;
+
+        if oracle != *oracle_info.key {
+            return Err(MarketError::OraclePubkeyMismatch.into());
+        }
+
+        apply_oracle_price(
+            &mut market_settings,
+            oracle_info,
+            spread_basis_points,
+            max_staleness_slots,
+        )?;
+    }
+
+    let buyer_record_info = if market_settings.limits.max_tokens_per_buyer.is_some() {
+        Some(next_account_info(account_iter)?)
+    } else {
+        None
+    };
+
+    // As well as the auction price
+    if let PricingMode::DutchAuction {
+        start_price,
+        end_price,
+        start_time,
+        end_time,
+    } = market_settings.pricing
+    {
+        let now = Clock::get()?.unix_timestamp;
+        market_settings.buy_price =
+            auction_price(start_price, end_price, start_time, end_time, now);
+    }
+
+    // The constant product market prices trades off its reserves before the trade
+    let reserves = if market_settings.pricing == PricingMode::ConstantProduct {
+        let reserves = Reserves {
+            quote: quote.balance(quote.market_info)?,
+            tokens: market_token_account.amount,
+        };
+
+        if reserves.quote == 0 || reserves.tokens == 0 {
+            return Err(MarketError::InsufficientLiquidity.into());
+        }
+
+        Some(reserves)
+    } else {
+        None
+    };
+
+    // Only the wallets of the allowlist can buy, proving it with `BuyWithProof`
+    if market_settings.allowlist_root.is_some()
+        && matches!(
+            operation,
+            OperationType::Buy { .. }
+                | OperationType::BuyExactLamports { .. }
+                | OperationType::BuyWithSignedQuote { .. }
+        )
+    {
+        return Err(MarketError::NotAllowlisted.into());
+    }
+
+    let mut accounts = TradeAccounts {
+        client_info,
+        client_token_info,
+        market_settings_info,
+        market_token_info,
+        fee_recipient_info,
+        quote,
+        buyer_record_info,
+    };
+
+    match operation {
+        OperationType::Buy {
+            tokens,
+            max_lamports,
+        } => {
+            if tokens == 0 {
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            let (lamports, fee) = buy_cost(&market_settings, reserves.as_ref(), tokens)?;
+            let total_lamports = lamports
+                .checked_add(fee)
+                .ok_or(MarketError::TooManyLamports)?;
+
+            if matches!(max_lamports, Some(max_lamports) if total_lamports > max_lamports) {
+                return Err(MarketError::PriceLimitExceeded.into());
+            }
+
+            process_buy(tokens, lamports, fee, None, &mut market_settings, &accounts)
+        }
+        OperationType::BuyExactLamports {
+            lamports,
+            min_tokens,
+        } => {
+            let tokens = tokens_for_lamports(
+                &market_settings,
+                reserves.as_ref(),
+                market_token_account.amount,
+                lamports,
+            )?;
+
+            if tokens == 0 {
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if tokens < min_tokens {
+                return Err(MarketError::PriceLimitExceeded.into());
+            }
+
+            let (lamports, fee) = buy_cost(&market_settings, reserves.as_ref(), tokens)?;
+            process_buy(tokens, lamports, fee, None, &mut market_settings, &accounts)
+        }
+        OperationType::Sell {
+            tokens,
+            min_lamports,
+        } => {
+            if tokens == 0 {
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if market_settings.pricing.is_curve() && tokens > market_settings.tokens_sold {
+                return Err(MarketError::CurveSupplyExceeded.into());
+            }
+
+            let lamports = sell_proceeds(&market_settings, reserves.as_ref(), tokens)?;
+            let fee = market_settings
+                .fee(lamports)
+                .ok_or(MarketError::TooManyLamports)?;
+
+            // Can't overflow: the fee is not greater than the price
+            if matches!(min_lamports, Some(min_lamports) if lamports - fee < min_lamports) {
+                return Err(MarketError::PriceLimitExceeded.into());
+            }
+
+            process_sell(tokens, lamports, fee, &mut market_settings, &accounts)
+        }
+        OperationType::BuyWithSignedQuote {
+            tokens,
+            quote: signed_quote,
+        } => {
+            let instructions_info = next_account_info(account_iter)?;
+            let nonce_info = next_account_info(account_iter)?;
+
+            verify_signed_quote(
+                &market_settings,
+                &accounts,
+                instructions_info,
+                &signed_quote,
+            )?;
+
+            if tokens == 0 {
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if tokens > signed_quote.max_tokens {
+                return Err(MarketError::QuoteTokensExceeded.into());
+            }
+
+            if Clock::get()?.slot > signed_quote.expiry_slot {
+                return Err(MarketError::QuoteExpired.into());
+            }
+
+            use_quote_nonce(
+                client_info,
+                nonce_info,
+                &market_settings.mint,
+                signed_quote.nonce,
+            )?;
+
+            let lamports = market_settings
+                .cost_at_price(tokens, signed_quote.price)
+                .ok_or(MarketError::TooManyLamports)?;
+            let fee = market_settings
+                .fee(lamports)
+                .ok_or(MarketError::TooManyLamports)?;
+
+            process_buy(tokens, lamports, fee, None, &mut market_settings, &accounts)
+        }
+        OperationType::BuyWithProof {
+            tokens,
+            allocation,
+            proof,
+        } => {
+            let entry = AllowlistEntry {
+                wallet: *client_info.key,
+                allocation,
+            };
+            match market_settings.allowlist_root {
+                Some(root) if allowlist::verify(&root, entry.leaf(), &proof) => {}
+                _ => return Err(MarketError::NotAllowlisted.into()),
+            }
+
+            // The allocation is counted in the buyer record even without a per-buyer limit
+            if allocation.is_some() && accounts.buyer_record_info.is_none() {
+                accounts.buyer_record_info = Some(next_account_info(account_iter)?);
+            }
+
+            if tokens == 0 {
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            let (lamports, fee) = buy_cost(&market_settings, reserves.as_ref(), tokens)?;
+            process_buy(
+                tokens,
+                lamports,
+                fee,
+                allocation,
+                &mut market_settings,
+                &accounts,
+            )
+        }
     }
 }
@@ -0,0 +1,165 @@
+use crate::{
+    error::MarketError,
+    processor::{check_system_program, check_token_program},
+    state::{MarketSettings, LAMPORTS_SEED, SETTINGS_SEED},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_pack::Pack,
+    system_instruction,
+};
+
+/// Moves all tokens of a token account owned by the settings PDA to `destination_info`
+/// and closes it, its rent goes to the admin
+fn close_token_account<'info>(
+    token_info: &AccountInfo<'info>,
+    destination_info: &AccountInfo<'info>,
+    admin_info: &AccountInfo<'info>,
+    market_settings_info: &AccountInfo<'info>,
+    settings_seed: &[&[u8]],
+) -> ProgramResult {
+    let token_account = spl_token::state::Account::unpack(&token_info.data.borrow())?;
+
+    if token_account.amount > 0 {
+        let ix = spl_token::instruction::transfer(
+            &spl_token::id(),
+            token_info.key,
+            destination_info.key,
+            market_settings_info.key,
+            &[market_settings_info.key],
+            token_account.amount,
+        )?;
+
+        msg!("Withdraw {} tokens from the market", token_account.amount);
+        invoke_signed(
+            &ix,
+            &[
+                market_settings_info.clone(),
+                token_info.clone(),
+                destination_info.clone(),
+            ],
+            &[settings_seed],
+        )?;
+    }
+
+    let ix = spl_token::instruction::close_account(
+        &spl_token::id(),
+        token_info.key,
+        admin_info.key,
+        market_settings_info.key,
+        &[market_settings_info.key],
+    )?;
+
+    msg!("Close tokens account");
+    invoke_signed(
+        &ix,
+        &[
+            token_info.clone(),
+            admin_info.clone(),
+            market_settings_info.clone(),
+        ],
+        &[settings_seed],
+    )
+}
+
+pub fn process_close_market(accounts: &[AccountInfo]) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_settings_info = next_account_info(account_iter)?;
+    let market_token_info = next_account_info(account_iter)?;
+    let market_lamports_info = next_account_info(account_iter)?;
+    let token_destination_info = next_account_info(account_iter)?;
+    let lamports_destination_info = next_account_info(account_iter)?;
+    let token_program_info = next_account_info(account_iter)?;
+    let system_program_info = next_account_info(account_iter)?;
+
+    if !admin_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    check_token_program(token_program_info)?;
+    check_system_program(system_program_info)?;
+
+    let market_settings = MarketSettings::load(market_settings_info)?;
+    let mint = &market_settings.mint;
+    let settings_bump = MarketSettings::settings_pubkey_with_bump(mint).1;
+    let (lamports_pubkey, lamports_bump) = MarketSettings::lamports_account_pubkey(mint);
+
+    if *market_token_info.key != MarketSettings::token_pubkey_with_bump(mint).0 {
+        return Err(MarketError::TokenPubkeyMismatch.into());
+    }
+
+    if *market_lamports_info.key != lamports_pubkey {
+        return Err(MarketError::LamportsPubkeyMismatch.into());
+    }
+
+    let quote_infos = if market_settings.quote_mint.is_some() {
+        let quote_vault_info = next_account_info(account_iter)?;
+        let quote_destination_info = next_account_info(account_iter)?;
+
+        if *quote_vault_info.key != MarketSettings::quote_vault_pubkey_with_bump(mint).0 {
+            return Err(MarketError::QuoteVaultPubkeyMismatch.into());
+        }
+
+        Some((quote_vault_info, quote_destination_info))
+    } else {
+        None
+    };
+
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    let settings_seed: &[&[_]] = &[SETTINGS_SEED.as_bytes(), mint.as_ref(), &[settings_bump]];
+    close_token_account(
+        market_token_info,
+        token_destination_info,
+        admin_info,
+        market_settings_info,
+        settings_seed,
+    )?;
+
+    if let Some((quote_vault_info, quote_destination_info)) = quote_infos {
+        close_token_account(
+            quote_vault_info,
+            quote_destination_info,
+            admin_info,
+            market_settings_info,
+            settings_seed,
+        )?;
+    }
+
+    let lamports = market_lamports_info.lamports();
+    if lamports > 0 {
+        let lamports_seed: &[&[_]] = &[LAMPORTS_SEED.as_bytes(), mint.as_ref(), &[lamports_bump]];
+        let ix = system_instruction::transfer(
+            market_lamports_info.key,
+            lamports_destination_info.key,
+            lamports,
+        );
+
+        msg!("Withdraw {} lamports from the market", lamports);
+        invoke_signed(
+            &ix,
+            &[
+                market_lamports_info.clone(),
+                lamports_destination_info.clone(),
+            ],
+            &[lamports_seed],
+        )?;
+    }
+
+    msg!("Close settings account");
+    let admin_lamports = admin_info
+        .lamports()
+        .checked_add(market_settings_info.lamports())
+        .ok_or(MarketError::TooManyLamports)?;
+    **admin_info.lamports.borrow_mut() = admin_lamports;
+    **market_settings_info.lamports.borrow_mut() = 0;
+    market_settings_info.data.borrow_mut().fill(0);
+
+    Ok(())
+}
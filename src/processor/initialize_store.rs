@@ -1,8 +1,9 @@
 use crate::{
     error::MarketError,
     id,
-    instruction::PriceArgs,
-    state::{MarketSettings, SETTINGS_SEED, TOKEN_SEED},
+    instruction::InitializeArgs,
+    processor::{check_system_program, check_token_program},
+    state::{MarketSettings, MAX_FEE_BASIS_POINTS, QUOTE_SEED, SETTINGS_SEED, TOKEN_SEED},
 };
 use borsh::BorshSerialize;
 use solana_program::{
@@ -14,25 +15,53 @@ use solana_program::{
     program_pack::Pack,
     rent::Rent,
     system_instruction,
-    sysvar::Sysvar,
+    sysvar::{self, Sysvar},
 };
 
 fn init_settings_account<'info>(
     admin_info: &AccountInfo<'info>,
     market_info: &AccountInfo<'info>,
     mint_info: &AccountInfo<'info>,
-    settings: &PriceArgs,
+    settings: &InitializeArgs,
 ) -> ProgramResult {
     let market_settings = MarketSettings {
         admin: *admin_info.key,
-        sell_price: settings.sell_price,
-        buy_price: settings.buy_price,
+        sell_price: settings.prices.sell_price,
+        buy_price: settings.prices.buy_price,
         mint: *mint_info.key,
+        reserve_sells: settings.reserve_sells,
+        deposited_tokens: 0,
+        fee_basis_points: settings.fee.fee_basis_points,
+        fee_recipient: settings.fee.fee_recipient,
+        paused: false,
+        pending_admin: None,
+        quote_mint: settings.quote_mint,
+        decimals: spl_token::state::Mint::unpack(&mint_info.data.borrow())?.decimals,
+        price_denominator: settings.prices.price_denominator,
+        pricing: settings.pricing,
+        tokens_sold: 0,
+        tokens_sold_total: 0,
+        limits: settings.limits,
+        allowlist_root: None,
+        admin_multisig: None,
+        price_authority: *admin_info.key,
+        treasury_authority: *admin_info.key,
+        price_timelock: None,
+        pending_price: None,
+        pending_price_timelock: None,
+        price_band: None,
+        min_spread_basis_points: settings.min_spread_basis_points,
+        last_price_update: 0,
+        quote_signer: None,
     };
 
-    let (settings_pubkey, bump_seed) = MarketSettings::settings_pubkey_with_bump();
-    let space = market_settings.try_to_vec()?.len();
-    let signers_seed: &[&[_]] = &[SETTINGS_SEED.as_bytes(), &[bump_seed]];
+    let (settings_pubkey, bump_seed) = MarketSettings::settings_pubkey_with_bump(mint_info.key);
+    let space = MarketSettings::LEN;
+    let signers_seed: &[&[_]] = &[
+        SETTINGS_SEED.as_bytes(),
+        mint_info.key.as_ref(),
+        &[bump_seed],
+    ];
 
     let rent = Rent::get()?;
     let lamports = rent.minimum_balance(space);
@@ -55,15 +84,15 @@ fn init_settings_account<'info>(
     Ok(())
 }
 
+/// Creates a token account of `mint_info` owned by the settings PDA at the PDA of `signers_seed`
 fn init_token_account<'info>(
     admin_info: &AccountInfo<'info>,
     token_info: &AccountInfo<'info>,
     market_settings_info: &AccountInfo<'info>,
     mint_info: &AccountInfo<'info>,
     rent_info: &AccountInfo<'info>,
+    signers_seed: &[&[u8]],
 ) -> ProgramResult {
-    let (token_pubkey, bump_seed) = MarketSettings::token_pubkey_with_bump();
-    let signers_seed: &[&[_]] = &[TOKEN_SEED.as_bytes(), &[bump_seed]];
     let space = spl_token::state::Account::LEN;
     let rent = Rent::from_account_info(rent_info)?;
     let lamports = rent.minimum_balance(space);
@@ -72,7 +101,7 @@ fn init_token_account<'info>(
     invoke_signed(
         &system_instruction::create_account(
             admin_info.key,
-            &token_pubkey,
+            token_info.key,
             lamports,
             space as u64,
             &spl_token::id(),
@@ -100,36 +129,109 @@ fn init_token_account<'info>(
     Ok(())
 }
 
-pub fn process_initialize_store(accounts: &[AccountInfo], settings: PriceArgs) -> ProgramResult {
+pub fn process_initialize_store(
+    accounts: &[AccountInfo],
+    settings: InitializeArgs,
+) -> ProgramResult {
     let account_iter = &mut accounts.iter();
 
     let admin_info = next_account_info(account_iter)?;
     let market_settings_info = next_account_info(account_iter)?;
     let market_tokens_info = next_account_info(account_iter)?;
     let mint_info = next_account_info(account_iter)?;
-    let _token_program = next_account_info(account_iter)?;
-    let _system_program = next_account_info(account_iter)?;
+    let token_program_info = next_account_info(account_iter)?;
+    let system_program_info = next_account_info(account_iter)?;
     let rent_info = next_account_info(account_iter)?;
 
     if !admin_info.is_signer {
         return Err(ProgramError::MissingRequiredSignature);
     }
 
+    check_token_program(token_program_info)?;
+    check_system_program(system_program_info)?;
+
+    if *rent_info.key != sysvar::rent::id() {
+        return Err(MarketError::RentSysvarMismatch.into());
+    }
+
+    if *mint_info.owner != spl_token::id() {
+        return Err(MarketError::MintOwnerMismatch.into());
+    }
+
+    if settings.prices.price_denominator == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if settings.fee.fee_basis_points > MAX_FEE_BASIS_POINTS {
+        return Err(MarketError::InvalidFee.into());
+    }
+
+    if !settings.pricing.is_valid() || !settings.limits.is_valid() {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if settings.min_spread_basis_points > MAX_FEE_BASIS_POINTS {
+        return Err(MarketError::InvalidSpread.into());
+    }
+
+    settings.pricing.check_spread(
+        settings.prices.sell_price,
+        settings.prices.buy_price,
+        settings.min_spread_basis_points,
+    )?;
+
     if !market_settings_info.data_is_empty() || !market_tokens_info.data_is_empty() {
         return Err(ProgramError::AccountAlreadyInitialized);
     }
 
-    if *market_tokens_info.key != MarketSettings::token_pubkey_with_bump().0 {
+    if *market_settings_info.key != MarketSettings::settings_pubkey_with_bump(mint_info.key).0 {
+        return Err(MarketError::SettingsPubkeyMismatch.into());
+    }
+
+    let (token_pubkey, token_bump) = MarketSettings::token_pubkey_with_bump(mint_info.key);
+    if *market_tokens_info.key != token_pubkey {
         return Err(MarketError::TokenPubkeyMismatch.into());
     }
 
+    let token_seed: &[&[_]] = &[TOKEN_SEED.as_bytes(), mint_info.key.as_ref(), &[token_bump]];
     init_token_account(
         admin_info,
         market_tokens_info,
         market_settings_info,
         mint_info,
         rent_info,
+        token_seed,
     )?;
+
+    if let Some(quote_mint) = settings.quote_mint {
+        let quote_vault_info = next_account_info(account_iter)?;
+        let quote_mint_info = next_account_info(account_iter)?;
+
+        if *quote_mint_info.key != quote_mint || quote_mint == *mint_info.key {
+            return Err(MarketError::UnsupportedMint.into());
+        }
+
+        if *quote_mint_info.owner != spl_token::id() {
+            return Err(MarketError::MintOwnerMismatch.into());
+        }
+
+        let (quote_vault_pubkey, quote_bump) =
+            MarketSettings::quote_vault_pubkey_with_bump(mint_info.key);
+        if *quote_vault_info.key != quote_vault_pubkey {
+            return Err(MarketError::QuoteVaultPubkeyMismatch.into());
+        }
+
+        let quote_seed: &[&[_]] = &[QUOTE_SEED.as_bytes(), mint_info.key.as_ref(), &[quote_bump]];
+        init_token_account(
+            admin_info,
+            quote_vault_info,
+            market_settings_info,
+            quote_mint_info,
+            rent_info,
+            quote_seed,
+        )?;
+    }
+
     init_settings_account(admin_info, market_settings_info, mint_info, &settings)?;
 
     Ok(())
@@ -0,0 +1,147 @@
+use crate::{
+    error::MarketError,
+    processor::check_token_program,
+    state::{MarketSettings, SETTINGS_SEED},
+};
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+/// `signer_infos` are the other signers of the admin multisig, if the admin keeps the treasury
+fn load_treasury_settings(
+    treasury_authority_info: &AccountInfo,
+    market_settings_info: &AccountInfo,
+    market_token_info: &AccountInfo,
+    signer_infos: &[AccountInfo],
+) -> Result<MarketSettings, ProgramError> {
+    if !treasury_authority_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let market_settings = MarketSettings::load(market_settings_info)?;
+    let mint = &market_settings.mint;
+
+    if *market_token_info.key != MarketSettings::token_pubkey_with_bump(mint).0 {
+        return Err(MarketError::TokenPubkeyMismatch.into());
+    }
+
+    market_settings.check_role(
+        &market_settings.treasury_authority,
+        treasury_authority_info,
+        signer_infos,
+    )?;
+
+    Ok(market_settings)
+}
+
+pub fn process_withdraw_tokens(
+    accounts: &[AccountInfo],
+    amount: u64,
+    destination: Pubkey,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let treasury_authority_info = next_account_info(account_iter)?;
+    let market_settings_info = next_account_info(account_iter)?;
+    let market_token_info = next_account_info(account_iter)?;
+    let destination_info = next_account_info(account_iter)?;
+    let token_program_info = next_account_info(account_iter)?;
+
+    check_token_program(token_program_info)?;
+
+    let mut market_settings = load_treasury_settings(
+        treasury_authority_info,
+        market_settings_info,
+        market_token_info,
+        account_iter.as_slice(),
+    )?;
+
+    if amount == 0 || *destination_info.key != destination {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let ix = spl_token::instruction::transfer(
+        &spl_token::id(),
+        market_token_info.key,
+        destination_info.key,
+        market_settings_info.key,
+        &[market_settings_info.key],
+        amount,
+    )?;
+
+    let mint = &market_settings.mint;
+    let bump = MarketSettings::settings_pubkey_with_bump(mint).1;
+    let seed: &[&[_]] = &[SETTINGS_SEED.as_bytes(), mint.as_ref(), &[bump]];
+
+    msg!("Withdraw {} tokens from the market", amount);
+    invoke_signed(
+        &ix,
+        &[
+            market_settings_info.clone(),
+            market_token_info.clone(),
+            destination_info.clone(),
+        ],
+        &[seed],
+    )?;
+
+    market_settings.deposited_tokens = market_settings.deposited_tokens.saturating_sub(amount);
+    market_settings.serialize(&mut *market_settings_info.data.borrow_mut())?;
+
+    Ok(())
+}
+
+pub fn process_deposit_tokens(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let treasury_authority_info = next_account_info(account_iter)?;
+    let market_settings_info = next_account_info(account_iter)?;
+    let treasury_token_info = next_account_info(account_iter)?;
+    let market_token_info = next_account_info(account_iter)?;
+    let token_program_info = next_account_info(account_iter)?;
+
+    check_token_program(token_program_info)?;
+
+    let mut market_settings = load_treasury_settings(
+        treasury_authority_info,
+        market_settings_info,
+        market_token_info,
+        account_iter.as_slice(),
+    )?;
+
+    if amount == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let ix = spl_token::instruction::transfer(
+        &spl_token::id(),
+        treasury_token_info.key,
+        market_token_info.key,
+        treasury_authority_info.key,
+        &[treasury_authority_info.key],
+        amount,
+    )?;
+
+    msg!("Deposit {} tokens to the market", amount);
+    invoke(
+        &ix,
+        &[
+            treasury_token_info.clone(),
+            market_token_info.clone(),
+            treasury_authority_info.clone(),
+        ],
+    )?;
+
+    market_settings.deposited_tokens = market_settings
+        .deposited_tokens
+        .checked_add(amount)
+        .ok_or(ProgramError::InvalidArgument)?;
+    market_settings.serialize(&mut *market_settings_info.data.borrow_mut())?;
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use crate::state::MarketSettings;
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+};
+
+pub fn process_update_allowlist(accounts: &[AccountInfo], root: Option<[u8; 32]>) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    market_settings.allowlist_root = root;
+
+    if root.is_some() {
+        msg!("Updating the allowlist");
+    } else {
+        msg!("Removing the allowlist");
+    }
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
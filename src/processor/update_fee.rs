@@ -0,0 +1,38 @@
+use crate::{
+    error::MarketError,
+    instruction::FeeArgs,
+    state::{MarketSettings, MAX_FEE_BASIS_POINTS},
+};
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+};
+
+pub fn process_update_fee(accounts: &[AccountInfo], fee: FeeArgs) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    if fee.fee_basis_points > MAX_FEE_BASIS_POINTS {
+        return Err(MarketError::InvalidFee.into());
+    }
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    market_settings.fee_basis_points = fee.fee_basis_points;
+    market_settings.fee_recipient = fee.fee_recipient;
+
+    msg!(
+        "Updating fee to {} basis points for {}",
+        fee.fee_basis_points,
+        fee.fee_recipient
+    );
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
@@ -1,36 +1,213 @@
-use crate::{error::MarketError, instruction::PriceArgs, state::MarketSettings};
-use borsh::{BorshDeserialize, BorshSerialize};
+use crate::{
+    error::MarketError,
+    instruction::PriceArgs,
+    state::{MarketSettings, PendingPrice, PendingTimelock, PriceBand},
+};
+use borsh::BorshSerialize;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    clock::Clock,
     entrypoint::ProgramResult,
     msg,
     program_error::ProgramError,
+    sysvar::Sysvar,
 };
 
 pub fn process_update_price(accounts: &[AccountInfo], settings: PriceArgs) -> ProgramResult {
     let account_iter = &mut accounts.iter();
 
+    let price_authority_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    if settings.price_denominator == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+    market_settings.check_role(
+        &market_settings.price_authority,
+        price_authority_info,
+        account_iter.as_slice(),
+    )?;
+
+    market_settings.pricing.check_spread(
+        settings.sell_price,
+        settings.buy_price,
+        market_settings.min_spread_basis_points,
+    )?;
+
+    // A pending update which has taken effect isn't replaced by the new one
+    let now = Clock::get()?.unix_timestamp;
+    market_settings.apply_pending_price(now);
+    market_settings.apply_pending_price_timelock(now);
+
+    if let Some(price_band) = market_settings.price_band {
+        if now
+            < market_settings
+                .last_price_update
+                .saturating_add(price_band.min_update_interval)
+        {
+            return Err(MarketError::PriceUpdateTooSoon.into());
+        }
+
+        // The band is around the last prices set, even if they haven't taken effect yet
+        let (previous_sell_price, previous_buy_price, previous_denominator) =
+            match market_settings.pending_price {
+                Some(pending_price) => (
+                    pending_price.sell_price,
+                    pending_price.buy_price,
+                    pending_price.price_denominator,
+                ),
+                None => (
+                    market_settings.sell_price,
+                    market_settings.buy_price,
+                    market_settings.price_denominator,
+                ),
+            };
+
+        let denominator = settings.price_denominator;
+        if !price_band.allows(
+            previous_sell_price,
+            previous_denominator,
+            settings.sell_price,
+            denominator,
+        ) || !price_band.allows(
+            previous_buy_price,
+            previous_denominator,
+            settings.buy_price,
+            denominator,
+        ) {
+            return Err(MarketError::PriceChangeOutOfBand.into());
+        }
+    }
+    market_settings.last_price_update = now;
+
+    match market_settings.price_timelock {
+        Some(price_timelock) => {
+            let effective_at = now
+                .checked_add(price_timelock)
+                .ok_or(ProgramError::InvalidArgument)?;
+            market_settings.pending_price = Some(PendingPrice {
+                sell_price: settings.sell_price,
+                buy_price: settings.buy_price,
+                price_denominator: settings.price_denominator,
+                effective_at,
+            });
+
+            msg!("Updating price from {}", effective_at);
+        }
+        None => {
+            market_settings.sell_price = settings.sell_price;
+            market_settings.buy_price = settings.buy_price;
+            market_settings.price_denominator = settings.price_denominator;
+
+            msg!("Updating price");
+        }
+    }
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
+
+pub fn process_update_price_band(
+    accounts: &[AccountInfo],
+    price_band: Option<PriceBand>,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
     let admin_info = next_account_info(account_iter)?;
     let market_info = next_account_info(account_iter)?;
 
-    if !admin_info.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
+    if matches!(price_band, Some(price_band) if !price_band.is_valid()) {
+        return Err(ProgramError::InvalidArgument);
     }
 
-    let expected_settings_pubkey = MarketSettings::settings_pubkey_with_bump().0;
-    if *market_info.key != expected_settings_pubkey {
-        return Err(MarketError::SettingsPubkeyMismatch.into());
+    let mut market_settings = MarketSettings::load(market_info)?;
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    market_settings.price_band = price_band;
+
+    msg!("Updating the price band to {:?}", price_band);
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
+
+pub fn process_update_price_timelock(
+    accounts: &[AccountInfo],
+    price_timelock: Option<i64>,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    if matches!(price_timelock, Some(price_timelock) if price_timelock <= 0) {
+        return Err(ProgramError::InvalidArgument);
     }
 
-    let mut market_settings = MarketSettings::try_from_slice(&market_info.data.borrow())?;
-    if market_settings.admin != *admin_info.key {
-        return Err(ProgramError::IllegalOwner);
+    let mut market_settings = MarketSettings::load(market_info)?;
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    let now = Clock::get()?.unix_timestamp;
+    market_settings.apply_pending_price_timelock(now);
+
+    // A shorter timelock or its removal waits for the current timelock, so a stolen admin key
+    // can't lift it and reprice at once. A longer one, which also drops such a change, is
+    // set immediately
+    let is_shorter = match (market_settings.price_timelock, price_timelock) {
+        (Some(current), Some(new)) => new < current,
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+    match market_settings.price_timelock {
+        Some(current) if is_shorter => {
+            let effective_at = now
+                .checked_add(current)
+                .ok_or(ProgramError::InvalidArgument)?;
+            market_settings.pending_price_timelock = Some(PendingTimelock {
+                price_timelock,
+                effective_at,
+            });
+
+            msg!(
+                "Updating the price timelock to {:?} seconds from {}",
+                price_timelock,
+                effective_at
+            );
+        }
+        _ => {
+            market_settings.price_timelock = price_timelock;
+            market_settings.pending_price_timelock = None;
+
+            msg!(
+                "Updating the price timelock to {:?} seconds",
+                price_timelock
+            );
+        }
     }
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
+
+pub fn process_cancel_price_update(accounts: &[AccountInfo]) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
 
-    market_settings.sell_price = settings.sell_price;
-    market_settings.buy_price = settings.buy_price;
+    // Prices which have taken effect stay
+    market_settings.apply_pending_price(Clock::get()?.unix_timestamp);
+    if market_settings.pending_price.is_none() {
+        return Err(ProgramError::InvalidArgument);
+    }
+    market_settings.pending_price = None;
 
-    msg!("Updating price");
+    msg!("Cancelling the price update");
     market_settings.serialize(&mut *market_info.data.borrow_mut())?;
 
     Ok(())
@@ -0,0 +1,55 @@
+use crate::{instruction::RolesArgs, secp256k1::ETH_ADDRESS_BYTES, state::MarketSettings};
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+};
+
+pub fn process_update_roles(accounts: &[AccountInfo], roles: RolesArgs) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    market_settings.price_authority = roles.price_authority;
+    market_settings.treasury_authority = roles.treasury_authority;
+
+    msg!(
+        "Updating the price authority to {} and the treasury authority to {}",
+        roles.price_authority,
+        roles.treasury_authority
+    );
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
+
+pub fn process_update_quote_signer(
+    accounts: &[AccountInfo],
+    quote_signer: Option<[u8; ETH_ADDRESS_BYTES]>,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    market_settings.quote_signer = quote_signer;
+
+    if quote_signer.is_some() {
+        msg!("Updating the quote key");
+    } else {
+        msg!("Removing the quote key, quotes are disabled");
+    }
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
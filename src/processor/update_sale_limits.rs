@@ -0,0 +1,30 @@
+use crate::state::{MarketSettings, SaleLimits};
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+};
+
+pub fn process_update_sale_limits(accounts: &[AccountInfo], limits: SaleLimits) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let admin_info = next_account_info(account_iter)?;
+    let market_info = next_account_info(account_iter)?;
+
+    if !limits.is_valid() {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut market_settings = MarketSettings::load(market_info)?;
+
+    market_settings.check_admin(admin_info, account_iter.as_slice())?;
+
+    market_settings.limits = limits;
+
+    msg!("Updating sale limits to {:?}", limits);
+    market_settings.serialize(&mut *market_info.data.borrow_mut())?;
+
+    Ok(())
+}
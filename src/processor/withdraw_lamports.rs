@@ -0,0 +1,152 @@
+use crate::{
+    error::MarketError,
+    processor::{check_system_program, check_token_program},
+    state::{MarketSettings, PricingMode, LAMPORTS_SEED, SETTINGS_SEED},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_pack::Pack,
+    system_instruction,
+    sysvar::Sysvar,
+};
+
+fn sells_reserve(
+    market_settings: &MarketSettings,
+    market_token_info: &AccountInfo,
+    mint_info: &AccountInfo,
+) -> Result<u64, ProgramError> {
+    // The constant product market never pays out more than its reserves hold
+    if market_settings.pricing == PricingMode::ConstantProduct {
+        return Ok(0);
+    }
+
+    let market_token_account = spl_token::state::Account::unpack(&market_token_info.data.borrow())?;
+    let mint = spl_token::state::Mint::unpack(&mint_info.data.borrow())?;
+    let mut circulating_tokens = mint.supply.saturating_sub(market_token_account.amount);
+
+    // On a curve the market buys back only the tokens it sold
+    if market_settings.pricing.is_curve() {
+        circulating_tokens = circulating_tokens.min(market_settings.tokens_sold);
+    }
+
+    market_settings
+        .sell_proceeds(circulating_tokens)
+        .ok_or_else(|| MarketError::TooManyLamports.into())
+}
+
+pub fn process_withdraw_lamports(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+
+    let treasury_authority_info = next_account_info(account_iter)?;
+    let market_settings_info = next_account_info(account_iter)?;
+    let market_lamports_info = next_account_info(account_iter)?;
+    let destination_info = next_account_info(account_iter)?;
+    let market_token_info = next_account_info(account_iter)?;
+    let mint_info = next_account_info(account_iter)?;
+    let system_program_info = next_account_info(account_iter)?;
+    let market_quote_info = next_account_info(account_iter)?;
+    let token_program_info = next_account_info(account_iter)?;
+
+    if !treasury_authority_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    check_system_program(system_program_info)?;
+    check_token_program(token_program_info)?;
+
+    if amount == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut market_settings = MarketSettings::load(market_settings_info)?;
+    // The reserve follows the prices in effect, the settings aren't written back
+    market_settings.apply_pending_price(Clock::get()?.unix_timestamp);
+    let mint = &market_settings.mint;
+
+    if *market_token_info.key != MarketSettings::token_pubkey_with_bump(mint).0 {
+        return Err(MarketError::TokenPubkeyMismatch.into());
+    }
+
+    if mint_info.key != mint {
+        return Err(MarketError::UnsupportedMint.into());
+    }
+
+    let (lamports_pubkey, bump) = MarketSettings::lamports_account_pubkey(mint);
+    if *market_lamports_info.key != lamports_pubkey {
+        return Err(MarketError::LamportsPubkeyMismatch.into());
+    }
+
+    market_settings.check_role(
+        &market_settings.treasury_authority,
+        treasury_authority_info,
+        account_iter.as_slice(),
+    )?;
+
+    let quote_vault_info = if market_settings.quote_mint.is_some() {
+        if *market_quote_info.key != MarketSettings::quote_vault_pubkey_with_bump(mint).0 {
+            return Err(MarketError::QuoteVaultPubkeyMismatch.into());
+        }
+
+        Some(market_quote_info)
+    } else {
+        None
+    };
+
+    let balance = match quote_vault_info {
+        Some(quote_vault_info) => {
+            spl_token::state::Account::unpack(&quote_vault_info.data.borrow())?.amount
+        }
+        None => market_lamports_info.lamports(),
+    };
+    let remaining = balance
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+
+    if market_settings.reserve_sells
+        && remaining < sells_reserve(&market_settings, market_token_info, mint_info)?
+    {
+        return Err(MarketError::InsufficientReserve.into());
+    }
+
+    if let Some(quote_vault_info) = quote_vault_info {
+        let bump = MarketSettings::settings_pubkey_with_bump(mint).1;
+        let seed: &[&[_]] = &[SETTINGS_SEED.as_bytes(), mint.as_ref(), &[bump]];
+        let ix = spl_token::instruction::transfer(
+            &spl_token::id(),
+            quote_vault_info.key,
+            destination_info.key,
+            market_settings_info.key,
+            &[market_settings_info.key],
+            amount,
+        )?;
+
+        msg!("Withdraw {} quote tokens from the market", amount);
+        invoke_signed(
+            &ix,
+            &[
+                quote_vault_info.clone(),
+                destination_info.clone(),
+                market_settings_info.clone(),
+            ],
+            &[seed],
+        )?;
+    } else {
+        let seed: &[&[_]] = &[LAMPORTS_SEED.as_bytes(), mint.as_ref(), &[bump]];
+        let ix =
+            system_instruction::transfer(market_lamports_info.key, destination_info.key, amount);
+
+        msg!("Withdraw {} lamports from the market", amount);
+        invoke_signed(
+            &ix,
+            &[market_lamports_info.clone(), destination_info.clone()],
+            &[seed],
+        )?;
+    }
+
+    Ok(())
+}
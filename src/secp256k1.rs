@@ -0,0 +1,128 @@
+//! Instructions of the secp256k1 signature verification program.
+//!
+//! The program is a precompile the 1.7 runtime enforces: a transaction with an instruction
+//! whose signature doesn't verify is rejected as a whole, so the market only has to check
+//! what the instruction verified. A signer is identified by its Ethereum address, the last
+//! 20 bytes of the keccak256 hash of its uncompressed public key, and signs the keccak256 hash
+//! of the message. The instruction data is laid out as follows, offsets are little endian `u16`:
+//!
+//! | Offset | Size | Field                                             |
+//! |--------|------|---------------------------------------------------|
+//! | 0      | 1    | Number of signatures, the market expects 1        |
+//! | 1      | 11   | Offsets of the parts below and their instructions |
+//! | 12     | 20   | Ethereum address                                  |
+//! | 32     | 64   | Signature                                         |
+//! | 96     | 1    | Recovery id                                       |
+//! | 97     | ..   | Message                                           |
+//!
+//! Instruction indexes are `u8` positions in the transaction, the market accepts only
+//! the index of the verification instruction itself.
+
+use solana_program::{instruction::Instruction, keccak};
+use std::convert::TryInto;
+
+pub use solana_program::secp256k1_program::id;
+
+pub const ETH_ADDRESS_BYTES: usize = 20;
+pub const SIGNATURE_BYTES: usize = 64;
+
+const OFFSETS_START: usize = 1;
+const OFFSETS_LEN: usize = 11;
+const ETH_ADDRESS_OFFSET: usize = OFFSETS_START + OFFSETS_LEN;
+const SIGNATURE_OFFSET: usize = ETH_ADDRESS_OFFSET + ETH_ADDRESS_BYTES;
+const MESSAGE_OFFSET: usize = SIGNATURE_OFFSET + SIGNATURE_BYTES + 1;
+
+/// Offsets of a verified signature's parts and the instructions holding them,
+/// the signature itself is only checked by the runtime
+struct SignatureOffsets {
+    signature_instruction_index: u8,
+    eth_address_offset: u16,
+    eth_address_instruction_index: u8,
+    message_data_offset: u16,
+    message_data_size: u16,
+    message_instruction_index: u8,
+}
+
+impl SignatureOffsets {
+    fn unpack(data: &[u8]) -> Option<Self> {
+        let offsets = data.get(OFFSETS_START..OFFSETS_START + OFFSETS_LEN)?;
+        let offset = |index: usize| u16::from_le_bytes([offsets[index], offsets[index + 1]]);
+
+        Some(SignatureOffsets {
+            signature_instruction_index: offsets[2],
+            eth_address_offset: offset(3),
+            eth_address_instruction_index: offsets[5],
+            message_data_offset: offset(6),
+            message_data_size: offset(8),
+            message_instruction_index: offsets[10],
+        })
+    }
+}
+
+/// Ethereum address of an uncompressed public key without its `0x04` prefix
+pub fn eth_address(pubkey: &[u8; 64]) -> [u8; ETH_ADDRESS_BYTES] {
+    let mut address = [0; ETH_ADDRESS_BYTES];
+    address.copy_from_slice(&keccak::hash(pubkey).0[32 - ETH_ADDRESS_BYTES..]);
+    address
+}
+
+/// Verification of `signature` of `message` by `eth_address`,
+/// to be placed at `instruction_index` of the transaction
+pub fn new_secp256k1_instruction(
+    eth_address: &[u8; ETH_ADDRESS_BYTES],
+    signature: &[u8; SIGNATURE_BYTES],
+    recovery_id: u8,
+    message: &[u8],
+    instruction_index: u8,
+) -> Instruction {
+    let mut data = Vec::with_capacity(MESSAGE_OFFSET + message.len());
+    data.push(1);
+    data.extend_from_slice(&(SIGNATURE_OFFSET as u16).to_le_bytes());
+    data.push(instruction_index);
+    data.extend_from_slice(&(ETH_ADDRESS_OFFSET as u16).to_le_bytes());
+    data.push(instruction_index);
+    data.extend_from_slice(&(MESSAGE_OFFSET as u16).to_le_bytes());
+    data.extend_from_slice(&(message.len() as u16).to_le_bytes());
+    data.push(instruction_index);
+    data.extend_from_slice(eth_address);
+    data.extend_from_slice(signature);
+    data.push(recovery_id);
+    data.extend_from_slice(message);
+
+    Instruction {
+        program_id: id(),
+        accounts: vec![],
+        data,
+    }
+}
+
+/// The signer and the message of the verification instruction at `instruction_index`
+/// with a single signature
+pub fn signed_message(
+    instruction: &Instruction,
+    instruction_index: u8,
+) -> Option<([u8; ETH_ADDRESS_BYTES], &[u8])> {
+    let data = &instruction.data;
+    if instruction.program_id != id() || *data.first()? != 1 {
+        return None;
+    }
+
+    let offsets = SignatureOffsets::unpack(data)?;
+    if offsets.signature_instruction_index != instruction_index
+        || offsets.eth_address_instruction_index != instruction_index
+        || offsets.message_instruction_index != instruction_index
+    {
+        return None;
+    }
+
+    let address_start = usize::from(offsets.eth_address_offset);
+    let eth_address = data
+        .get(address_start..address_start + ETH_ADDRESS_BYTES)?
+        .try_into()
+        .ok()?;
+    let message_start = usize::from(offsets.message_data_offset);
+    let message =
+        data.get(message_start..message_start + usize::from(offsets.message_data_size))?;
+
+    Some((eth_address, message))
+}
@@ -1,29 +1,669 @@
-use crate::id;
+use crate::{error::MarketError, id, secp256k1::ETH_ADDRESS_BYTES};
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::pubkey::Pubkey;
+use solana_program::{
+    account_info::AccountInfo, borsh::try_from_slice_unchecked, entrypoint::ProgramResult,
+    program_error::ProgramError, pubkey::Pubkey,
+};
+use std::convert::TryInto;
 
 pub const SETTINGS_SEED: &str = "settings_seed";
 pub const TOKEN_SEED: &str = "token_seed";
 pub const LAMPORTS_SEED: &str = "lamports_seed";
+pub const QUOTE_SEED: &str = "quote_seed";
+pub const NONCE_SEED: &str = "nonce_seed";
+pub const BUYER_SEED: &str = "buyer_seed";
+pub const BLOCKED_SEED: &str = "blocked_seed";
 
+pub const MAX_FEE_BASIS_POINTS: u16 = 10_000;
+
+pub const MAX_ADMIN_SIGNERS: usize = 5;
+
+/// Fixed point scale of the exponential curve's growth factor
+const CURVE_SCALE: u128 = 1_000_000_000_000;
+
+/// How the price of a token is set, curve prices are of `price_denominator` whole tokens
+/// and depend on the number of tokens sold by the market. Each whole token has a single price,
+/// so the market buys a token back for the price it was sold at
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub enum PricingMode {
+    /// `sell_price` and `buy_price` set by the admin
+    Fixed,
+    /// The n-th whole token costs `base_price + slope * n`
+    Linear { base_price: u64, slope: u64 },
+    /// The n-th whole token costs `base_price * (1 + growth_basis_points / 10000)^n`
+    Exponential {
+        base_price: u64,
+        growth_basis_points: u16,
+    },
+    /// Prices follow the market's quote and token reserves, keeping their product from decreasing
+    ConstantProduct,
+    /// `buy_price` and `sell_price` are set around the price published in the `oracle` account
+    /// before each trade, `spread_basis_points` above and below it. Prices published more than
+    /// `max_staleness_slots` ago are rejected
+    Oracle {
+        oracle: Pubkey,
+        spread_basis_points: u16,
+        max_staleness_slots: u64,
+    },
+    /// `buy_price` decays linearly from `start_price` at `start_time` to `end_price`
+    /// at `end_time`, unix timestamps. Tokens are bought back for `sell_price`
+    DutchAuction {
+        start_price: u64,
+        end_price: u64,
+        start_time: i64,
+        end_time: i64,
+    },
+}
+
+impl PricingMode {
+    /// Whether every token is traded for `buy_price` and `sell_price`
+    pub fn is_flat(&self) -> bool {
+        matches!(
+            self,
+            PricingMode::Fixed | PricingMode::Oracle { .. } | PricingMode::DutchAuction { .. }
+        )
+    }
+
+    /// Whether the parameters of the mode are consistent
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            PricingMode::Oracle {
+                spread_basis_points,
+                ..
+            } => spread_basis_points <= MAX_FEE_BASIS_POINTS,
+            PricingMode::DutchAuction {
+                start_price,
+                end_price,
+                start_time,
+                end_time,
+            } => start_price >= end_price && start_time < end_time,
+            _ => true,
+        }
+    }
+
+    /// Checks that tokens can't be bought for less than they are sold back for,
+    /// the spread between the prices is at least `min_spread_basis_points` of the buy price.
+    /// The auction's buy price falls to its end price, other modes set both prices themselves
+    pub fn check_spread(
+        &self,
+        sell_price: u64,
+        buy_price: u64,
+        min_spread_basis_points: u16,
+    ) -> ProgramResult {
+        let buy_price = match *self {
+            PricingMode::Fixed => buy_price,
+            PricingMode::DutchAuction { end_price, .. } => end_price,
+            _ => return Ok(()),
+        };
+
+        let spread = buy_price
+            .checked_sub(sell_price)
+            .ok_or(MarketError::InvalidSpread)?;
+        if u128::from(spread) * u128::from(MAX_FEE_BASIS_POINTS)
+            < u128::from(buy_price) * u128::from(min_spread_basis_points)
+        {
+            return Err(MarketError::InvalidSpread.into());
+        }
+
+        Ok(())
+    }
+
+    /// Whether the price depends on the number of tokens sold
+    pub fn is_curve(&self) -> bool {
+        matches!(
+            self,
+            PricingMode::Linear { .. } | PricingMode::Exponential { .. }
+        )
+    }
+
+    /// Total price of the first `tokens` smallest units on the curve,
+    /// multiplied by the smallest units in a whole token to keep the fractions
+    pub fn curve_total(&self, tokens: u64, decimals: u8) -> Option<u128> {
+        let unit = 10u128.checked_pow(decimals.into())?;
+        let whole_tokens = u128::from(tokens) / unit;
+        let rest = u128::from(tokens) % unit;
+
+        match *self {
+            PricingMode::Fixed
+            | PricingMode::ConstantProduct
+            | PricingMode::Oracle { .. }
+            | PricingMode::DutchAuction { .. } => None,
+            PricingMode::Linear { base_price, slope } => {
+                let base_price = u128::from(base_price);
+                let slope = u128::from(slope);
+                // Arithmetic series of the whole tokens' prices
+                let whole_total =
+                    whole_tokens
+                        .checked_mul(base_price)?
+                        .checked_add(slope.checked_mul(
+                            whole_tokens.checked_mul(whole_tokens.saturating_sub(1))? / 2,
+                        )?)?;
+                let price = slope.checked_mul(whole_tokens)?.checked_add(base_price)?;
+
+                whole_total
+                    .checked_mul(unit)?
+                    .checked_add(rest.checked_mul(price)?)
+            }
+            PricingMode::Exponential {
+                base_price,
+                growth_basis_points,
+            } => {
+                let base_price = u128::from(base_price);
+                // Geometric series of the first `n` whole tokens' prices
+                let whole_total = |n: u128| -> Option<u128> {
+                    if growth_basis_points == 0 {
+                        return n.checked_mul(base_price)?.checked_mul(unit);
+                    }
+                    let growth = growth_factor(growth_basis_points, n.try_into().ok()?)?;
+                    Some(
+                        base_price
+                            .checked_mul(growth.checked_sub(CURVE_SCALE)?)?
+                            .checked_mul(unit)?
+                            / (u128::from(growth_basis_points) * CURVE_SCALE
+                                / u128::from(MAX_FEE_BASIS_POINTS)),
+                    )
+                };
+                let total = whole_total(whole_tokens)?;
+                if rest == 0 {
+                    return Some(total);
+                }
+                // The next token's price comes from the same series,
+                // so the total never goes past the next whole token's
+                let price = whole_total(whole_tokens.checked_add(1)?)?.checked_sub(total)?;
+
+                total.checked_add(rest.checked_mul(price)? / unit)
+            }
+        }
+    }
+}
+
+/// `(1 + growth_basis_points / 10000)^n` multiplied by `CURVE_SCALE`, rounded down
+fn growth_factor(growth_basis_points: u16, mut n: u64) -> Option<u128> {
+    let mut factor = CURVE_SCALE
+        + u128::from(growth_basis_points) * CURVE_SCALE / u128::from(MAX_FEE_BASIS_POINTS);
+    let mut result = CURVE_SCALE;
+
+    while n > 0 {
+        if n & 1 == 1 {
+            result = result.checked_mul(factor)? / CURVE_SCALE;
+        }
+        n >>= 1;
+        if n > 0 {
+            factor = factor.checked_mul(factor)? / CURVE_SCALE;
+        }
+    }
+
+    Some(result)
+}
+
+/// Schedule and limits of the market's sales, buying back tokens isn't limited.
+/// Times are unix timestamps
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct SaleLimits {
+    /// Tokens can't be bought before
+    pub opens_at: Option<i64>,
+    /// Tokens can't be bought from
+    pub closes_at: Option<i64>,
+    /// Limit of `tokens_sold_total`, so tokens bought back can't be sold again
+    pub max_tokens_sold: Option<u64>,
+    /// Limit of tokens bought by a single client, tracked in their `BuyerRecord`
+    pub max_tokens_per_buyer: Option<u64>,
+}
+
+impl SaleLimits {
+    pub const LEN: usize = 9 + 9 + 9 + 9;
+
+    pub fn is_valid(&self) -> bool {
+        match (self.opens_at, self.closes_at) {
+            (Some(opens_at), Some(closes_at)) => opens_at < closes_at,
+            _ => true,
+        }
+    }
+}
+
+/// M-of-N signer set administering the market instead of the single admin key
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct AdminMultisig {
+    /// Number of signatures required, M
+    pub threshold: u8,
+    /// Up to `MAX_ADMIN_SIGNERS` distinct signers, N
+    pub signers: Vec<Pubkey>,
+}
+
+impl AdminMultisig {
+    pub const LEN: usize = 1 + 4 + 32 * MAX_ADMIN_SIGNERS;
+
+    pub fn is_valid(&self) -> bool {
+        let distinct = self
+            .signers
+            .iter()
+            .enumerate()
+            .all(|(index, signer)| !self.signers[..index].contains(signer));
+
+        self.threshold > 0
+            && usize::from(self.threshold) <= self.signers.len()
+            && self.signers.len() <= MAX_ADMIN_SIGNERS
+            && distinct
+    }
+}
+
+/// Prices set by a timelocked price update, in effect from `effective_at`, a unix timestamp
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub struct PendingPrice {
+    pub sell_price: u64,
+    pub buy_price: u64,
+    pub price_denominator: u64,
+    pub effective_at: i64,
+}
+
+impl PendingPrice {
+    pub const LEN: usize = 8 + 8 + 8 + 8;
+}
+
+/// A shorter price timelock or its removal, in effect from `effective_at`, a unix timestamp
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub struct PendingTimelock {
+    pub price_timelock: Option<i64>,
+    pub effective_at: i64,
+}
+
+impl PendingTimelock {
+    pub const LEN: usize = 9 + 8;
+}
+
+/// Limits of each price update
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub struct PriceBand {
+    /// The largest change of a price per whole token, in basis points of the previous price
+    pub max_change_basis_points: u16,
+    /// Seconds from one price update to the next
+    pub min_update_interval: i64,
+}
+
+impl PriceBand {
+    pub const LEN: usize = 2 + 8;
+
+    pub fn is_valid(&self) -> bool {
+        self.min_update_interval >= 0
+    }
+
+    /// Whether `price` of `denominator` whole tokens is within the band
+    /// around `previous_price` of `previous_denominator` whole tokens
+    pub fn allows(
+        &self,
+        previous_price: u64,
+        previous_denominator: u64,
+        price: u64,
+        denominator: u64,
+    ) -> bool {
+        // Both prices per `previous_denominator * denominator` whole tokens
+        let previous = u128::from(previous_price) * u128::from(denominator);
+        let new = u128::from(price) * u128::from(previous_denominator);
+        let max_fee = u128::from(MAX_FEE_BASIS_POINTS);
+        let basis_points = u128::from(self.max_change_basis_points);
+        // `previous * basis_points / max_fee` rounded down, no change exceeds it once it saturates
+        let max_change = (previous / max_fee)
+            .saturating_mul(basis_points)
+            .saturating_add(previous % max_fee * basis_points / max_fee);
+
+        new.max(previous) - new.min(previous) <= max_change
+    }
+}
+
+/// Tokens bought by a client from a market with a per-buyer limit
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default)]
+pub struct BuyerRecord {
+    pub tokens_bought: u64,
+}
+
+impl BuyerRecord {
+    pub const LEN: usize = 8;
+
+    pub fn from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        Ok(try_from_slice_unchecked(data)?)
+    }
+}
+
+/// Prices, fees and lamports amounts are in lamports or,
+/// if the market has a quote mint, in the smallest units of the quote token
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct MarketSettings {
+    /// The owner, in charge of the roles and everything not delegated to them
     pub admin: Pubkey,
+    /// Price of `price_denominator` whole tokens
     pub sell_price: u64,
+    /// Price of `price_denominator` whole tokens
     pub buy_price: u64,
     pub mint: Pubkey,
+    pub reserve_sells: bool,
+    /// Tokens deposited by the admin and not withdrawn yet
+    pub deposited_tokens: u64,
+    /// Fee charged on both buys and sells, in basis points of the transaction's price
+    pub fee_basis_points: u16,
+    /// System account or, with a quote mint, a token account of the quote mint
+    pub fee_recipient: Pubkey,
+    /// Trades are rejected while the market is paused
+    pub paused: bool,
+    /// Proposed by the admin and set as the admin once they accept
+    pub pending_admin: Option<Pubkey>,
+    /// Mint of the token the market is paid in, native SOL if `None`
+    pub quote_mint: Option<Pubkey>,
+    /// Decimals of the mint, read at the initialization
+    pub decimals: u8,
+    pub price_denominator: u64,
+    pub pricing: PricingMode,
+    /// Tokens sold by the market and not bought back, the position on the price curve
+    pub tokens_sold: u64,
+    /// Tokens sold by the market in total, buybacks don't lower it
+    pub tokens_sold_total: u64,
+    pub limits: SaleLimits,
+    /// Root of the Merkle tree of wallets allowed to buy, anyone can buy if `None`
+    pub allowlist_root: Option<[u8; 32]>,
+    /// Admin instructions need the signatures of the multisig instead of the admin's one
+    pub admin_multisig: Option<AdminMultisig>,
+    /// Updates the prices
+    pub price_authority: Pubkey,
+    /// Withdraws and deposits the market's lamports and tokens
+    pub treasury_authority: Pubkey,
+    /// Seconds between a price update and the new prices taking effect, immediately if `None`
+    pub price_timelock: Option<i64>,
+    /// Prices of the last timelocked update until they take effect
+    pub pending_price: Option<PendingPrice>,
+    /// A shorter timelock waits for the current one to pass
+    pub pending_price_timelock: Option<PendingTimelock>,
+    pub price_band: Option<PriceBand>,
+    /// The smallest difference between the buy and the sell prices,
+    /// in basis points of the buy price
+    pub min_spread_basis_points: u16,
+    /// Unix timestamp of the last price update
+    pub last_price_update: i64,
+    /// Ethereum address of the secp256k1 key the admin signs quotes with, the runtime
+    /// only verifies secp256k1 signatures. Quotes are disabled if `None`
+    pub quote_signer: Option<[u8; ETH_ADDRESS_BYTES]>,
 }
 
 impl MarketSettings {
-    pub fn settings_pubkey_with_bump() -> (Pubkey, u8) {
-        Pubkey::find_program_address(&[SETTINGS_SEED.as_bytes()], &id())
+    /// Size of the settings account, optional fields are counted as present
+    pub const LEN: usize = 32
+        + 8
+        + 8
+        + 32
+        + 1
+        + 8
+        + 2
+        + 32
+        + 1
+        + 33
+        + 33
+        + 1
+        + 8
+        + 43
+        + 8
+        + 8
+        + SaleLimits::LEN
+        + 33
+        + 1
+        + AdminMultisig::LEN
+        + 32
+        + 32
+        + 9
+        + 1
+        + PendingPrice::LEN
+        + 1
+        + PendingTimelock::LEN
+        + 1
+        + PriceBand::LEN
+        + 2
+        + 8
+        + 1
+        + ETH_ADDRESS_BYTES;
+
+    /// Deserializes settings ignoring the unused space left by optional fields
+    pub fn from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        Ok(try_from_slice_unchecked(data)?)
+    }
+
+    /// Settings of an account owned by the program
+    pub fn from_account_info(info: &AccountInfo) -> Result<Self, ProgramError> {
+        if *info.owner != id() {
+            return Err(MarketError::SettingsOwnerMismatch.into());
+        }
+
+        Self::from_account_data(&info.data.borrow())
+    }
+
+    /// Settings of a market, checking that `settings_info` is its settings PDA
+    pub fn load(settings_info: &AccountInfo) -> Result<Self, ProgramError> {
+        let settings = Self::from_account_info(settings_info)?;
+        if *settings_info.key != Self::settings_pubkey_with_bump(&settings.mint).0 {
+            return Err(MarketError::SettingsPubkeyMismatch.into());
+        }
+
+        Ok(settings)
+    }
+
+    pub fn settings_pubkey_with_bump(mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SETTINGS_SEED.as_bytes(), mint.as_ref()], &id())
+    }
+
+    pub fn token_pubkey_with_bump(mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[TOKEN_SEED.as_bytes(), mint.as_ref()], &id())
+    }
+
+    pub fn lamports_account_pubkey(mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[LAMPORTS_SEED.as_bytes(), mint.as_ref()], &id())
+    }
+
+    /// Token account of the quote mint, owned by the settings PDA
+    pub fn quote_vault_pubkey_with_bump(mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[QUOTE_SEED.as_bytes(), mint.as_ref()], &id())
+    }
+
+    /// Record of a used signed quote nonce
+    pub fn nonce_pubkey_with_bump(mint: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[NONCE_SEED.as_bytes(), mint.as_ref(), &nonce.to_le_bytes()],
+            &id(),
+        )
+    }
+
+    pub fn buyer_record_pubkey_with_bump(mint: &Pubkey, client: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[BUYER_SEED.as_bytes(), mint.as_ref(), client.as_ref()],
+            &id(),
+        )
+    }
+
+    /// Record of a wallet blocked from trading, exists only while the wallet is blocked
+    pub fn block_record_pubkey_with_bump(mint: &Pubkey, wallet: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[BLOCKED_SEED.as_bytes(), mint.as_ref(), wallet.as_ref()],
+            &id(),
+        )
+    }
+
+    /// Whether the block record exists, a closed record keeps its owner
+    /// until the end of the transaction but has no lamports
+    pub fn is_blocked(block_record_info: &AccountInfo) -> bool {
+        *block_record_info.owner == id() && block_record_info.lamports() > 0
     }
 
-    pub fn token_pubkey_with_bump() -> (Pubkey, u8) {
-        Pubkey::find_program_address(&[TOKEN_SEED.as_bytes()], &id())
+    /// Checks that the admin authorized the instruction. `admin_info` must sign as the admin or,
+    /// with a multisig, as one of its signers, `signer_infos` are the other signers
+    pub fn check_admin(
+        &self,
+        admin_info: &AccountInfo,
+        signer_infos: &[AccountInfo],
+    ) -> ProgramResult {
+        if !admin_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let multisig = match &self.admin_multisig {
+            Some(multisig) => multisig,
+            None if self.admin == *admin_info.key => return Ok(()),
+            None => return Err(ProgramError::IllegalOwner),
+        };
+
+        if !multisig.signers.contains(admin_info.key) {
+            return Err(ProgramError::IllegalOwner);
+        }
+
+        // Each signer of the multisig counts once
+        let signatures = multisig
+            .signers
+            .iter()
+            .filter(|signer| {
+                *signer == admin_info.key
+                    || signer_infos
+                        .iter()
+                        .any(|info| info.is_signer && info.key == *signer)
+            })
+            .count();
+        if signatures < usize::from(multisig.threshold) {
+            return Err(MarketError::NotEnoughAdminSigners.into());
+        }
+
+        Ok(())
+    }
+
+    /// Sets the pending price timelock if it has taken effect by `now`
+    pub fn apply_pending_price_timelock(&mut self, now: i64) {
+        if let Some(pending_timelock) = self.pending_price_timelock {
+            if now >= pending_timelock.effective_at {
+                self.price_timelock = pending_timelock.price_timelock;
+                self.pending_price_timelock = None;
+            }
+        }
+    }
+
+    /// Sets the pending prices if they have taken effect by `now`
+    pub fn apply_pending_price(&mut self, now: i64) {
+        if let Some(pending_price) = self.pending_price {
+            if now >= pending_price.effective_at {
+                self.sell_price = pending_price.sell_price;
+                self.buy_price = pending_price.buy_price;
+                self.price_denominator = pending_price.price_denominator;
+                self.pending_price = None;
+            }
+        }
+    }
+
+    /// Checks that `role` signed with `signer_info`. A role the admin keeps is checked
+    /// as the admin, so a multisig also covers it, `signer_infos` are its other signers
+    pub fn check_role(
+        &self,
+        role: &Pubkey,
+        signer_info: &AccountInfo,
+        signer_infos: &[AccountInfo],
+    ) -> ProgramResult {
+        if *role == self.admin {
+            return self.check_admin(signer_info, signer_infos);
+        }
+
+        if !signer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if role != signer_info.key {
+            return Err(ProgramError::IllegalOwner);
+        }
+
+        Ok(())
+    }
+
+    /// Smallest token units in `price_denominator` whole tokens
+    fn price_scale(&self) -> Option<u128> {
+        10u128
+            .checked_pow(self.decimals.into())?
+            .checked_mul(self.price_denominator.into())
+    }
+
+    /// Price of buying `tokens` smallest units for `price` of `price_denominator` whole tokens,
+    /// rounded up
+    pub fn cost_at_price(&self, tokens: u64, price: u64) -> Option<u64> {
+        let scale = self.price_scale()?;
+        u128::from(tokens)
+            .checked_mul(price.into())?
+            .checked_add(scale.checked_sub(1)?)?
+            .checked_div(scale)?
+            .try_into()
+            .ok()
+    }
+
+    /// Price of buying `tokens` smallest units, rounded up
+    pub fn buy_cost(&self, tokens: u64) -> Option<u64> {
+        let scale = self.price_scale()?;
+        let value = match self.pricing {
+            pricing if pricing.is_flat() => return self.cost_at_price(tokens, self.buy_price),
+            pricing => {
+                let tokens_sold = self.tokens_sold.checked_add(tokens)?;
+                pricing
+                    .curve_total(tokens_sold, self.decimals)?
+                    .checked_sub(pricing.curve_total(self.tokens_sold, self.decimals)?)?
+            }
+        };
+
+        value
+            .checked_add(scale.checked_sub(1)?)?
+            .checked_div(scale)?
+            .try_into()
+            .ok()
+    }
+
+    /// Price of selling `tokens` smallest units, rounded down.
+    /// On a curve the market can't buy back more tokens than it sold
+    pub fn sell_proceeds(&self, tokens: u64) -> Option<u64> {
+        let value = match self.pricing {
+            pricing if pricing.is_flat() => {
+                u128::from(tokens).checked_mul(self.sell_price.into())?
+            }
+            pricing => {
+                let tokens_sold = self.tokens_sold.checked_sub(tokens)?;
+                pricing
+                    .curve_total(self.tokens_sold, self.decimals)?
+                    .checked_sub(pricing.curve_total(tokens_sold, self.decimals)?)?
+            }
+        };
+
+        value.checked_div(self.price_scale()?)?.try_into().ok()
+    }
+
+    /// The largest number of smallest units whose buy cost doesn't exceed `lamports`,
+    /// on a curve at most `max_tokens`, the tokens the market has
+    pub fn tokens_for_cost(&self, lamports: u64, max_tokens: u64) -> Option<u64> {
+        if self.pricing.is_flat() {
+            let tokens = u128::from(lamports)
+                .checked_mul(self.price_scale()?)?
+                .checked_div(self.buy_price.into())?;
+
+            // Tokens can't be bought for more than there are
+            return Some(tokens.try_into().unwrap_or(u64::MAX));
+        }
+
+        // The cost grows with the number of tokens, costs that overflow are out of budget.
+        // Searching only the market's tokens keeps the curve evaluations few
+        let (mut low, mut high) = (0, max_tokens);
+        while low < high {
+            let middle = low + (high - low) / 2 + 1;
+            if matches!(self.buy_cost(middle), Some(cost) if cost <= lamports) {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+
+        Some(low)
     }
 
-    pub fn lamports_account_pubkey() -> (Pubkey, u8) {
-        Pubkey::find_program_address(&[LAMPORTS_SEED.as_bytes()], &id())
+    /// Fee for a transaction of `lamports`, rounded up
+    pub fn fee(&self, lamports: u64) -> Option<u64> {
+        let max_fee = u128::from(MAX_FEE_BASIS_POINTS);
+        u128::from(lamports)
+            .checked_mul(self.fee_basis_points.into())?
+            .checked_add(max_fee - 1)?
+            .checked_div(max_fee)?
+            .try_into()
+            .ok()
     }
 }
@@ -1,11 +1,42 @@
+use solana_program::instruction::Instruction;
 use solana_program::{program_pack::Pack, pubkey::Pubkey, rent::Rent, system_instruction};
 use solana_program_test::ProgramTestContext;
 use solana_sdk::{signature::Keypair, signer::Signer, transaction::Transaction};
 use spl_token::state::Account;
 
+fn token_account_instructions(
+    ctx: &ProgramTestContext,
+    token: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    tokens: u64,
+) -> Vec<Instruction> {
+    vec![
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            token,
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN.try_into().unwrap(),
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_account(&spl_token::id(), token, mint, owner).unwrap(),
+        spl_token::instruction::mint_to(
+            &spl_token::id(),
+            mint,
+            token,
+            &ctx.payer.pubkey(),
+            &[&ctx.payer.pubkey()],
+            tokens,
+        )
+        .unwrap(),
+    ]
+}
+
 pub struct TestClient {
     pub client: Keypair,
     pub client_token: Keypair,
+    /// Token account of the market's quote mint
+    pub client_quote: Option<Keypair>,
 }
 
 impl TestClient {
@@ -18,38 +49,24 @@ impl TestClient {
         let client = Keypair::new();
         let client_token = Keypair::new();
 
-        let ixs = [
-            system_instruction::transfer(&ctx.payer.pubkey(), &client.pubkey(), lamports),
-            system_instruction::create_account(
-                &ctx.payer.pubkey(),
-                &client_token.pubkey(),
-                Rent::default().minimum_balance(Account::LEN),
-                Account::LEN.try_into().unwrap(),
-                &spl_token::id(),
-            ),
-            spl_token::instruction::initialize_account(
-                &spl_token::id(),
-                &client_token.pubkey(),
-                mint,
-                &client.pubkey(),
-            )
-            .unwrap(),
-            spl_token::instruction::mint_to(
-                &spl_token::id(),
-                mint,
-                &client_token.pubkey(),
-                &ctx.payer.pubkey(),
-                &[&client.pubkey()],
-                tokens,
-            )
-            .unwrap(),
-        ];
+        let mut ixs = vec![system_instruction::transfer(
+            &ctx.payer.pubkey(),
+            &client.pubkey(),
+            lamports,
+        )];
+        ixs.extend(token_account_instructions(
+            ctx,
+            &client_token.pubkey(),
+            mint,
+            &client.pubkey(),
+            tokens,
+        ));
 
         ctx.banks_client
             .process_transaction(Transaction::new_signed_with_payer(
                 &ixs,
                 Some(&ctx.payer.pubkey()),
-                &[&ctx.payer, &client_token, &client],
+                &[&ctx.payer, &client_token],
                 ctx.last_blockhash,
             ))
             .await
@@ -58,9 +75,48 @@ impl TestClient {
         TestClient {
             client,
             client_token,
+            client_quote: None,
         }
     }
 
+    pub async fn add_quote_account(
+        &mut self,
+        ctx: &mut ProgramTestContext,
+        quote_mint: &Pubkey,
+        tokens: u64,
+    ) {
+        let client_quote = Keypair::new();
+        let ixs = token_account_instructions(
+            ctx,
+            &client_quote.pubkey(),
+            quote_mint,
+            &self.client.pubkey(),
+            tokens,
+        );
+
+        ctx.banks_client
+            .process_transaction(Transaction::new_signed_with_payer(
+                &ixs,
+                Some(&ctx.payer.pubkey()),
+                &[&ctx.payer, &client_quote],
+                ctx.last_blockhash,
+            ))
+            .await
+            .unwrap();
+
+        self.client_quote = Some(client_quote);
+    }
+
+    pub async fn get_quote_tokens_number(&self, ctx: &mut ProgramTestContext) -> u64 {
+        let client_quote = self.client_quote.as_ref().unwrap().pubkey();
+        let token_account = ctx
+            .banks_client
+            .get_packed_account_data::<spl_token::state::Account>(client_quote)
+            .await
+            .unwrap();
+        token_account.amount
+    }
+
     pub async fn add_lamports(&self, ctx: &mut ProgramTestContext, lamports: u64) {
         let ix = [system_instruction::transfer(
             &ctx.payer.pubkey(),
@@ -1,21 +1,97 @@
-use solana_program::{program_pack::Pack, pubkey::Pubkey, rent::Rent, system_instruction};
+use solana_program::{
+    instruction::{Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+};
 use solana_program_test::{processor, ProgramTest, ProgramTestContext};
-use solana_sdk::{signature::Keypair, signer::Signer, transaction::Transaction};
+use solana_sdk::{
+    account::Account,
+    signature::Keypair,
+    signer::Signer,
+    transaction::{Transaction, TransactionError},
+    transport::TransportError,
+};
 use spl_token::state::Mint;
-use tokenmarket::{entrypoint, id};
+use tokenmarket::{entrypoint, error::MarketError, id, state::MarketSettings};
 
 pub async fn get_context() -> ProgramTestContext {
-    let program_test = ProgramTest::new(
+    get_context_with_accounts(vec![]).await
+}
+
+/// Context with `accounts` created at genesis, such as hand-built oracle accounts
+pub async fn get_context_with_accounts(accounts: Vec<(Pubkey, Account)>) -> ProgramTestContext {
+    let mut program_test = ProgramTest::new(
         "tokenmarket",
         id(),
         processor!(entrypoint::process_instruction),
     );
+    for (address, account) in accounts {
+        program_test.add_account(address, account);
+    }
     program_test.start_with_context().await
 }
 
+/// Account at the settings PDA of a new mint, filled like settings but owned by another program
+pub fn foreign_settings() -> (Pubkey, Account) {
+    let mint = Pubkey::new_unique();
+    let account = Account {
+        lamports: 1_000_000_000,
+        data: vec![0; MarketSettings::LEN],
+        owner: Pubkey::new_unique(),
+        executable: false,
+        rent_epoch: 0,
+    };
+    (MarketSettings::settings_pubkey_with_bump(&mint).0, account)
+}
+
+/// Processes `ixs` signed by the payer and `signers`
+pub async fn process_instructions(
+    ctx: &mut ProgramTestContext,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), TransportError> {
+    let blockhash = ctx.banks_client.get_recent_blockhash().await.unwrap();
+    let mut all_signers = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction =
+        Transaction::new_signed_with_payer(ixs, Some(&ctx.payer.pubkey()), &all_signers, blockhash);
+    ctx.banks_client.process_transaction(transaction).await
+}
+
+/// Processes `ix` with the account at `index` replaced by `pubkey`
+pub async fn process_with_account(
+    ctx: &mut ProgramTestContext,
+    mut ix: Instruction,
+    index: usize,
+    pubkey: Pubkey,
+    signers: &[&Keypair],
+) -> Result<(), TransportError> {
+    ix.accounts[index].pubkey = pubkey;
+    process_instructions(ctx, &[ix], signers).await
+}
+
+/// Fails unless the transaction failed with the program's `error`
+pub fn assert_market_error(result: Result<(), TransportError>, error: MarketError) {
+    match result {
+        Err(TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, error as u32),
+        other => panic!("Expected {:?}, got {:?}", error, other),
+    }
+}
+
 pub async fn get_admin(ctx: &mut ProgramTestContext, lamports: u64) -> Keypair {
     let admin = Keypair::new();
-    let ix = system_instruction::transfer(&ctx.payer.pubkey(), &admin.pubkey(), lamports);
+    transfer_lamports(ctx, &admin.pubkey(), lamports).await;
+    admin
+}
+
+/// Sends `lamports` from the payer to `destination`
+pub async fn transfer_lamports(ctx: &mut ProgramTestContext, destination: &Pubkey, lamports: u64) {
+    let ix = system_instruction::transfer(&ctx.payer.pubkey(), destination, lamports);
     ctx.banks_client
         .process_transaction(Transaction::new_signed_with_payer(
             &[ix],
@@ -25,12 +101,14 @@ pub async fn get_admin(ctx: &mut ProgramTestContext, lamports: u64) -> Keypair {
         ))
         .await
         .unwrap();
-    admin
 }
 
 pub async fn generate_mint(ctx: &mut ProgramTestContext) -> Keypair {
+    generate_mint_with_decimals(ctx, 0).await
+}
+
+pub async fn generate_mint_with_decimals(ctx: &mut ProgramTestContext, decimals: u8) -> Keypair {
     let mint = Keypair::new();
-    let decimals = 0;
 
     let ixs = [
         system_instruction::create_account(
@@ -1,14 +1,17 @@
-use solana_program::{pubkey::Pubkey, system_instruction};
+use solana_program::{instruction::Instruction, pubkey::Pubkey, system_instruction};
 use solana_program_test::ProgramTestContext;
 use solana_sdk::{
     signature::Keypair, signer::Signer, transaction::Transaction, transport::TransportError,
 };
 use tokenmarket::{
-    instruction::{MarketInstructions, PriceArgs},
-    state::MarketSettings,
+    instruction::{FeeArgs, InitializeArgs, MarketInstructions, PriceArgs, RolesArgs},
+    secp256k1::ETH_ADDRESS_BYTES,
+    state::{AdminMultisig, MarketSettings, PriceBand, PricingMode, SaleLimits},
 };
 
-pub struct TestMarket {}
+pub struct TestMarket {
+    pub mint: Pubkey,
+}
 
 impl TestMarket {
     pub async fn new(
@@ -19,15 +22,27 @@ impl TestMarket {
         sell_price: u64,
         buy_price: u64,
     ) -> TestMarket {
-        let market = TestMarket {};
+        let market = TestMarket { mint: *mint };
         market
-            .initialize(ctx, admin, mint, tokens, sell_price, buy_price)
+            .initialize(ctx, admin, tokens, sell_price, buy_price)
             .await;
         market
     }
 
+    pub async fn new_with_args(
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        mint: &Pubkey,
+        tokens: u64,
+        args: InitializeArgs,
+    ) -> TestMarket {
+        let market = TestMarket { mint: *mint };
+        market.initialize_with_args(ctx, admin, tokens, args).await;
+        market
+    }
+
     pub async fn add_lamports(&self, ctx: &mut ProgramTestContext, lamports: u64) {
-        let pubkey = MarketSettings::lamports_account_pubkey().0;
+        let pubkey = MarketSettings::lamports_account_pubkey(&self.mint).0;
         let ix = [system_instruction::transfer(
             &ctx.payer.pubkey(),
             &pubkey,
@@ -49,23 +64,48 @@ impl TestMarket {
         &self,
         ctx: &mut ProgramTestContext,
         admin: &Keypair,
-        mint: &Pubkey,
         tokens: u64,
         sell_price: u64,
         buy_price: u64,
     ) {
-        let args = PriceArgs {
-            sell_price,
-            buy_price,
-        };
+        let args = TestMarket::initialize_args(&admin.pubkey(), sell_price, buy_price);
+        self.initialize_with_args(ctx, admin, tokens, args).await;
+    }
+
+    /// Arguments of a market with fixed prices and no fee
+    pub fn initialize_args(admin: &Pubkey, sell_price: u64, buy_price: u64) -> InitializeArgs {
+        InitializeArgs {
+            prices: PriceArgs {
+                sell_price,
+                buy_price,
+                price_denominator: 1,
+            },
+            fee: FeeArgs {
+                fee_basis_points: 0,
+                fee_recipient: *admin,
+            },
+            reserve_sells: false,
+            quote_mint: None,
+            pricing: PricingMode::Fixed,
+            limits: SaleLimits::default(),
+            min_spread_basis_points: 0,
+        }
+    }
 
-        let market_token_pubkey = MarketSettings::token_pubkey_with_bump().0;
+    pub async fn initialize_with_args(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        tokens: u64,
+        args: InitializeArgs,
+    ) {
+        let market_token_pubkey = MarketSettings::token_pubkey_with_bump(&self.mint).0;
 
         let ixs = [
-            MarketInstructions::initialize_store(&admin.pubkey(), mint, args),
+            MarketInstructions::initialize_store(&admin.pubkey(), &self.mint, args),
             spl_token::instruction::mint_to(
                 &spl_token::id(),
-                mint,
+                &self.mint,
                 &market_token_pubkey,
                 &ctx.payer.pubkey(),
                 &[&ctx.payer.pubkey()],
@@ -90,12 +130,25 @@ impl TestMarket {
         admin: &Keypair,
         sell_price: u64,
         buy_price: u64,
+    ) -> Result<(), TransportError> {
+        self.update_with_denominator(ctx, admin, sell_price, buy_price, 1)
+            .await
+    }
+
+    pub async fn update_with_denominator(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        sell_price: u64,
+        buy_price: u64,
+        price_denominator: u64,
     ) -> Result<(), TransportError> {
         let args = PriceArgs {
             sell_price,
             buy_price,
+            price_denominator,
         };
-        let ix = MarketInstructions::update_price(&admin.pubkey(), args);
+        let ix = MarketInstructions::update_price(&admin.pubkey(), &self.mint, args);
         ctx.banks_client
             .process_transaction(Transaction::new_signed_with_payer(
                 &[ix],
@@ -106,16 +159,365 @@ impl TestMarket {
             .await
     }
 
-    pub async fn get_settings(&self, ctx: &mut ProgramTestContext) -> MarketSettings {
-        let settings_pubkey = MarketSettings::settings_pubkey_with_bump().0;
+    pub async fn update_fee(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        fee_basis_points: u16,
+        fee_recipient: &Pubkey,
+    ) -> Result<(), TransportError> {
+        let args = FeeArgs {
+            fee_basis_points,
+            fee_recipient: *fee_recipient,
+        };
+        let ix = MarketInstructions::update_fee(&admin.pubkey(), &self.mint, args);
+        ctx.banks_client
+            .process_transaction(Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&admin.pubkey()),
+                &[admin],
+                ctx.last_blockhash,
+            ))
+            .await
+    }
+
+    pub async fn update_sale_limits(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        limits: SaleLimits,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::update_sale_limits(&admin.pubkey(), &self.mint, limits);
+        ctx.banks_client
+            .process_transaction(Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&admin.pubkey()),
+                &[admin],
+                ctx.last_blockhash,
+            ))
+            .await
+    }
+
+    pub async fn update_allowlist(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        root: Option<[u8; 32]>,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::update_allowlist(&admin.pubkey(), &self.mint, root);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    pub async fn block_wallet(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        wallet: &Pubkey,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::block_wallet(&admin.pubkey(), &self.mint, wallet);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    pub async fn unblock_wallet(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        wallet: &Pubkey,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::unblock_wallet(&admin.pubkey(), &self.mint, wallet);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    pub async fn update_price_timelock(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        price_timelock: Option<i64>,
+    ) -> Result<(), TransportError> {
+        let ix =
+            MarketInstructions::update_price_timelock(&admin.pubkey(), &self.mint, price_timelock);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    pub async fn update_price_band(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        price_band: Option<PriceBand>,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::update_price_band(&admin.pubkey(), &self.mint, price_band);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    pub async fn cancel_price_update(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::cancel_price_update(&admin.pubkey(), &self.mint);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    pub async fn update_roles(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        price_authority: &Pubkey,
+        treasury_authority: &Pubkey,
+    ) -> Result<(), TransportError> {
+        let args = RolesArgs {
+            price_authority: *price_authority,
+            treasury_authority: *treasury_authority,
+        };
+        let ix = MarketInstructions::update_roles(&admin.pubkey(), &self.mint, args);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    pub async fn update_quote_signer(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        quote_signer: Option<[u8; ETH_ADDRESS_BYTES]>,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::update_quote_signer(&admin.pubkey(), &self.mint, quote_signer);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    pub async fn set_paused(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        paused: bool,
+    ) -> Result<(), TransportError> {
+        let ix = if paused {
+            MarketInstructions::pause(&admin.pubkey(), &self.mint)
+        } else {
+            MarketInstructions::resume(&admin.pubkey(), &self.mint)
+        };
         ctx.banks_client
-            .get_account_data_with_borsh(settings_pubkey)
+            .process_transaction(Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&admin.pubkey()),
+                &[admin],
+                ctx.last_blockhash,
+            ))
+            .await
+    }
+
+    pub async fn propose_admin(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        new_admin: &Pubkey,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::propose_admin(&admin.pubkey(), &self.mint, new_admin);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    pub async fn accept_admin(
+        &self,
+        ctx: &mut ProgramTestContext,
+        new_admin: &Keypair,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::accept_admin(&new_admin.pubkey(), &self.mint);
+        self.process_admin_transaction(ctx, ix, new_admin).await
+    }
+
+    pub async fn cancel_admin_transfer(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::cancel_admin_transfer(&admin.pubkey(), &self.mint);
+        self.process_admin_transaction(ctx, ix, admin).await
+    }
+
+    /// `signers` are the signers of the admin multisig, the first one pays for the transaction
+    pub async fn set_admin_multisig(
+        &self,
+        ctx: &mut ProgramTestContext,
+        signers: &[&Keypair],
+        multisig: Option<AdminMultisig>,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::set_admin_multisig(&signers[0].pubkey(), &self.mint, multisig);
+        self.process_multisig_transaction(ctx, ix, signers).await
+    }
+
+    /// `signers` are the signers of the admin multisig, the first one pays for the transaction
+    pub async fn set_paused_with_signers(
+        &self,
+        ctx: &mut ProgramTestContext,
+        signers: &[&Keypair],
+        paused: bool,
+    ) -> Result<(), TransportError> {
+        let ix = if paused {
+            MarketInstructions::pause(&signers[0].pubkey(), &self.mint)
+        } else {
+            MarketInstructions::resume(&signers[0].pubkey(), &self.mint)
+        };
+        self.process_multisig_transaction(ctx, ix, signers).await
+    }
+
+    pub async fn withdraw_lamports_with_signers(
+        &self,
+        ctx: &mut ProgramTestContext,
+        signers: &[&Keypair],
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::withdraw_lamports(
+            &signers[0].pubkey(),
+            &self.mint,
+            destination,
+            amount,
+        );
+        self.process_multisig_transaction(ctx, ix, signers).await
+    }
+
+    async fn process_multisig_transaction(
+        &self,
+        ctx: &mut ProgramTestContext,
+        ix: Instruction,
+        signers: &[&Keypair],
+    ) -> Result<(), TransportError> {
+        let other_signers: Vec<_> = signers[1..].iter().map(|signer| signer.pubkey()).collect();
+        let other_signers: Vec<_> = other_signers.iter().collect();
+        let ix = MarketInstructions::with_admin_signers(ix, &other_signers);
+
+        let blockhash = ctx.banks_client.get_recent_blockhash().await.unwrap();
+        ctx.banks_client
+            .process_transaction(Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&signers[0].pubkey()),
+                signers,
+                blockhash,
+            ))
+            .await
+    }
+
+    async fn process_admin_transaction(
+        &self,
+        ctx: &mut ProgramTestContext,
+        ix: Instruction,
+        signer: &Keypair,
+    ) -> Result<(), TransportError> {
+        let blockhash = ctx.banks_client.get_recent_blockhash().await.unwrap();
+        ctx.banks_client
+            .process_transaction(Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&signer.pubkey()),
+                &[signer],
+                blockhash,
+            ))
+            .await
+    }
+
+    pub async fn withdraw_lamports(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Result<(), TransportError> {
+        let ix =
+            MarketInstructions::withdraw_lamports(&admin.pubkey(), &self.mint, destination, amount);
+        ctx.banks_client
+            .process_transaction(Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&admin.pubkey()),
+                &[admin],
+                ctx.last_blockhash,
+            ))
+            .await
+    }
+
+    pub async fn withdraw_tokens(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Result<(), TransportError> {
+        let ix =
+            MarketInstructions::withdraw_tokens(&admin.pubkey(), &self.mint, destination, amount);
+        ctx.banks_client
+            .process_transaction(Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&admin.pubkey()),
+                &[admin],
+                ctx.last_blockhash,
+            ))
+            .await
+    }
+
+    pub async fn deposit_tokens(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        admin_token: &Pubkey,
+        amount: u64,
+    ) -> Result<(), TransportError> {
+        let ix =
+            MarketInstructions::deposit_tokens(&admin.pubkey(), &self.mint, admin_token, amount);
+        ctx.banks_client
+            .process_transaction(Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&admin.pubkey()),
+                &[admin],
+                ctx.last_blockhash,
+            ))
+            .await
+    }
+
+    pub async fn close(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        token_destination: &Pubkey,
+        lamports_destination: &Pubkey,
+    ) -> Result<(), TransportError> {
+        self.close_with_quote(ctx, admin, token_destination, lamports_destination, None)
+            .await
+    }
+
+    pub async fn close_with_quote(
+        &self,
+        ctx: &mut ProgramTestContext,
+        admin: &Keypair,
+        token_destination: &Pubkey,
+        lamports_destination: &Pubkey,
+        quote_destination: Option<&Pubkey>,
+    ) -> Result<(), TransportError> {
+        let ix = MarketInstructions::close_market(
+            &admin.pubkey(),
+            &self.mint,
+            token_destination,
+            lamports_destination,
+            quote_destination,
+        );
+        ctx.banks_client
+            .process_transaction(Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&ctx.payer.pubkey()),
+                &[&ctx.payer, admin],
+                ctx.last_blockhash,
+            ))
+            .await
+    }
+
+    pub async fn get_settings(&self, ctx: &mut ProgramTestContext) -> MarketSettings {
+        let settings_pubkey = MarketSettings::settings_pubkey_with_bump(&self.mint).0;
+        let settings_account = ctx
+            .banks_client
+            .get_account(settings_pubkey)
             .await
             .unwrap()
+            .unwrap();
+        MarketSettings::from_account_data(&settings_account.data).unwrap()
     }
 
     pub async fn get_tokens_number(&self, ctx: &mut ProgramTestContext) -> u64 {
-        let token_pubkey = MarketSettings::token_pubkey_with_bump().0;
+        let token_pubkey = MarketSettings::token_pubkey_with_bump(&self.mint).0;
         let token_account = ctx
             .banks_client
             .get_packed_account_data::<spl_token::state::Account>(token_pubkey)
@@ -124,8 +526,18 @@ impl TestMarket {
         token_account.amount
     }
 
+    pub async fn get_quote_tokens_number(&self, ctx: &mut ProgramTestContext) -> u64 {
+        let quote_vault_pubkey = MarketSettings::quote_vault_pubkey_with_bump(&self.mint).0;
+        let token_account = ctx
+            .banks_client
+            .get_packed_account_data::<spl_token::state::Account>(quote_vault_pubkey)
+            .await
+            .unwrap();
+        token_account.amount
+    }
+
     pub async fn get_balance(&self, ctx: &mut ProgramTestContext) -> u64 {
-        let lamports_pubkey = MarketSettings::lamports_account_pubkey().0;
+        let lamports_pubkey = MarketSettings::lamports_account_pubkey(&self.mint).0;
         ctx.banks_client.get_balance(lamports_pubkey).await.unwrap()
     }
 }
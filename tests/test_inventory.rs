@@ -0,0 +1,151 @@
+use helpers::{
+    client::TestClient,
+    common::{
+        assert_market_error, foreign_settings, generate_mint, get_context,
+        get_context_with_accounts, process_with_account,
+    },
+    market::TestMarket,
+};
+use solana_program::system_program;
+use solana_program_test::tokio;
+use solana_sdk::signer::Signer;
+use tokenmarket::{error::MarketError, instruction::MarketInstructions};
+
+mod helpers;
+
+#[tokio::test]
+async fn deposit_and_withdraw_tokens() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+
+    let admin_tokens = 500;
+    let market_tokens = 100;
+
+    let admin = TestClient::new(ctx, &mint.pubkey(), 1_000_000_000, admin_tokens).await;
+    let client = TestClient::new(ctx, &mint.pubkey(), 1_000_000_000, 0).await;
+    let market = TestMarket::new(ctx, &admin.client, &mint.pubkey(), market_tokens, 1, 2).await;
+
+    let admin_token = admin.client_token.pubkey();
+    let client_token = client.client_token.pubkey();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    market
+        .deposit_tokens(ctx, &admin.client, &admin_token, 200)
+        .await
+        .unwrap();
+
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens + 200);
+    assert_eq!(admin.get_tokens_number(ctx).await, admin_tokens - 200);
+    assert_eq!(market.get_settings(ctx).await.deposited_tokens, 200);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    market
+        .withdraw_tokens(ctx, &admin.client, &admin_token, 50)
+        .await
+        .unwrap();
+
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens + 150);
+    assert_eq!(admin.get_tokens_number(ctx).await, admin_tokens - 150);
+    assert_eq!(market.get_settings(ctx).await.deposited_tokens, 150);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Unsold inventory can be recovered too
+    market
+        .withdraw_tokens(ctx, &admin.client, &client_token, market_tokens + 150)
+        .await
+        .unwrap();
+
+    assert_eq!(market.get_tokens_number(ctx).await, 0);
+    assert_eq!(client.get_tokens_number(ctx).await, market_tokens + 150);
+    assert_eq!(market.get_settings(ctx).await.deposited_tokens, 0);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // InsufficientTokens
+    assert!(market
+        .withdraw_tokens(ctx, &admin.client, &admin_token, 1)
+        .await
+        .is_err());
+
+    // InsufficientTokens
+    assert!(market
+        .deposit_tokens(ctx, &admin.client, &admin_token, admin_tokens)
+        .await
+        .is_err());
+
+    // Transfer 0 tokens is an error
+    assert!(market
+        .deposit_tokens(ctx, &admin.client, &admin_token, 0)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn inventory_admin_only() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+
+    let admin = TestClient::new(ctx, &mint.pubkey(), 1_000_000_000, 100).await;
+    let client = TestClient::new(ctx, &mint.pubkey(), 1_000_000_000, 100).await;
+    let market = TestMarket::new(ctx, &admin.client, &mint.pubkey(), 100, 1, 2).await;
+
+    let client_token = client.client_token.pubkey();
+
+    assert!(market
+        .withdraw_tokens(ctx, &client.client, &client_token, 10)
+        .await
+        .is_err());
+    assert!(market
+        .deposit_tokens(ctx, &client.client, &client_token, 10)
+        .await
+        .is_err());
+
+    assert_eq!(market.get_tokens_number(ctx).await, 100);
+    assert_eq!(client.get_tokens_number(ctx).await, 100);
+}
+
+#[tokio::test]
+async fn inventory_invalid_accounts() {
+    let (foreign_settings, foreign_settings_account) = foreign_settings();
+    let ctx =
+        &mut get_context_with_accounts(vec![(foreign_settings, foreign_settings_account)]).await;
+    let mint = generate_mint(ctx).await;
+
+    let admin = TestClient::new(ctx, &mint.pubkey(), 1_000_000_000, 100).await;
+    let market = TestMarket::new(ctx, &admin.client, &mint.pubkey(), 100, 1, 2).await;
+    let admin_pubkey = admin.client.pubkey();
+    let admin_token = admin.client_token.pubkey();
+    let signers = &[&admin.client];
+
+    let withdraw =
+        MarketInstructions::withdraw_tokens(&admin_pubkey, &mint.pubkey(), &admin_token, 10);
+    assert_market_error(
+        process_with_account(ctx, withdraw.clone(), 1, foreign_settings, signers).await,
+        MarketError::SettingsOwnerMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, withdraw, 4, system_program::id(), signers).await,
+        MarketError::TokenProgramMismatch,
+    );
+
+    let deposit =
+        MarketInstructions::deposit_tokens(&admin_pubkey, &mint.pubkey(), &admin_token, 10);
+    assert_market_error(
+        process_with_account(ctx, deposit.clone(), 1, foreign_settings, signers).await,
+        MarketError::SettingsOwnerMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, deposit, 4, system_program::id(), signers).await,
+        MarketError::TokenProgramMismatch,
+    );
+
+    assert_eq!(market.get_tokens_number(ctx).await, 100);
+    assert_eq!(admin.get_tokens_number(ctx).await, 100);
+}
@@ -1,9 +1,19 @@
 use helpers::{
-    common::{generate_mint, get_admin, get_context},
+    common::{
+        assert_market_error, foreign_settings, generate_mint, get_admin, get_context,
+        get_context_with_accounts, process_instructions, process_with_account,
+    },
     market::TestMarket,
 };
+use solana_program::{program_pack::Pack, pubkey::Pubkey, rent::Rent, system_program, sysvar};
 use solana_program_test::tokio;
-use solana_sdk::signer::Signer;
+use solana_sdk::{signature::Keypair, signer::Signer};
+use tokenmarket::{
+    error::MarketError,
+    instruction::{InitializeArgs, MarketInstructions},
+    secp256k1::ETH_ADDRESS_BYTES,
+    state::{AdminMultisig, MarketSettings, PriceBand},
+};
 
 use crate::helpers::client::TestClient;
 
@@ -69,12 +79,525 @@ async fn market_initalized_twice() {
     let admin = get_admin(ctx, 100_000_000).await;
     let mint = generate_mint(ctx).await;
 
-    let market = TestMarket {};
+    let market = TestMarket {
+        mint: mint.pubkey(),
+    };
+    market.initialize(ctx, &admin, 120, 0, 0).await;
+    market.initialize(ctx, &admin, 140, 0, 0).await;
+}
+
+#[tokio::test]
+async fn market_per_mint() {
+    let ctx = &mut get_context().await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let first_mint = generate_mint(ctx).await;
+    let second_mint = generate_mint(ctx).await;
+
+    let first = TestMarket::new(ctx, &admin, &first_mint.pubkey(), 100, 1, 2).await;
+    let second = TestMarket::new(ctx, &admin, &second_mint.pubkey(), 200, 3, 4).await;
+
+    let first_settings = first.get_settings(ctx).await;
+    let second_settings = second.get_settings(ctx).await;
+
+    assert_eq!(first_settings.mint, first_mint.pubkey());
+    assert_eq!(second_settings.mint, second_mint.pubkey());
+    assert_eq!(first.get_tokens_number(ctx).await, 100);
+    assert_eq!(second.get_tokens_number(ctx).await, 200);
+
+    first.update(ctx, &admin, 10, 20).await.unwrap();
+
+    let first_settings = first.get_settings(ctx).await;
+    let second_settings = second.get_settings(ctx).await;
+
+    assert_eq!(first_settings.sell_price, 10);
+    assert_eq!(first_settings.buy_price, 20);
+    assert_eq!(second_settings.sell_price, 3);
+    assert_eq!(second_settings.buy_price, 4);
+}
+
+#[tokio::test]
+async fn close_market() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let client = TestClient::new(ctx, &mint.pubkey(), 10000, 0).await;
+    let lamports_destination = Keypair::new();
+
+    let tokens = 300;
+    let lamports = 5000;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), tokens, 1, 2).await;
+    market.add_lamports(ctx, lamports).await;
+
+    let settings_pubkey = MarketSettings::settings_pubkey_with_bump(&mint.pubkey()).0;
+    let token_pubkey = MarketSettings::token_pubkey_with_bump(&mint.pubkey()).0;
+    let settings_rent = ctx.banks_client.get_balance(settings_pubkey).await.unwrap();
+    let token_rent = Rent::default().minimum_balance(spl_token::state::Account::LEN);
+    let admin_lamports = ctx.banks_client.get_balance(admin.pubkey()).await.unwrap();
+
+    // Only the admin can close the market
+    assert!(market
+        .close(
+            ctx,
+            &client.client,
+            &client.client_token.pubkey(),
+            &lamports_destination.pubkey(),
+        )
+        .await
+        .is_err());
+
+    market
+        .close(
+            ctx,
+            &admin,
+            &client.client_token.pubkey(),
+            &lamports_destination.pubkey(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(client.get_tokens_number(ctx).await, tokens);
+    assert_eq!(
+        ctx.banks_client
+            .get_balance(lamports_destination.pubkey())
+            .await
+            .unwrap(),
+        lamports
+    );
+    assert_eq!(
+        ctx.banks_client.get_balance(admin.pubkey()).await.unwrap(),
+        admin_lamports + settings_rent + token_rent
+    );
+    assert!(ctx
+        .banks_client
+        .get_account(settings_pubkey)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(ctx
+        .banks_client
+        .get_account(token_pubkey)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(market.get_balance(ctx).await, 0);
+
+    // The market for the mint can be opened again
+    market.initialize(ctx, &admin, 10, 3, 4).await;
+    assert_eq!(market.get_tokens_number(ctx).await, 10);
+    assert_eq!(market.get_settings(ctx).await.buy_price, 4);
+}
+
+#[tokio::test]
+async fn invalid_program_accounts() {
+    let (foreign_settings, foreign_settings_account) = foreign_settings();
+    let ctx =
+        &mut get_context_with_accounts(vec![(foreign_settings, foreign_settings_account)]).await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let destination = Keypair::new().pubkey();
+    let signers = &[&admin];
+
+    let args = TestMarket::initialize_args(&admin.pubkey(), 1, 2);
+    let initialize = MarketInstructions::initialize_store(&admin.pubkey(), &mint.pubkey(), args);
+    assert_market_error(
+        process_with_account(ctx, initialize.clone(), 4, Pubkey::new_unique(), signers).await,
+        MarketError::TokenProgramMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, initialize.clone(), 5, spl_token::id(), signers).await,
+        MarketError::SystemProgramMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, initialize.clone(), 6, sysvar::clock::id(), signers).await,
+        MarketError::RentSysvarMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, initialize, 3, foreign_settings, signers).await,
+        MarketError::MintOwnerMismatch,
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 1, 2).await;
+    let close = MarketInstructions::close_market(
+        &admin.pubkey(),
+        &mint.pubkey(),
+        &destination,
+        &destination,
+        None,
+    );
+    assert_market_error(
+        process_with_account(ctx, close.clone(), 1, foreign_settings, signers).await,
+        MarketError::SettingsOwnerMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, close.clone(), 6, system_program::id(), signers).await,
+        MarketError::TokenProgramMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, close, 7, Pubkey::new_unique(), signers).await,
+        MarketError::SystemProgramMismatch,
+    );
+
+    assert_eq!(market.get_tokens_number(ctx).await, 100);
+}
+
+#[tokio::test]
+async fn admin_transfer() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let new_admin = get_admin(ctx, 1_000_000_000).await;
+    let stranger = get_admin(ctx, 1_000_000_000).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 1, 2).await;
+
+    // Nobody can accept before a proposal and only the admin can propose
+    assert!(market.accept_admin(ctx, &new_admin).await.is_err());
+    assert!(market
+        .propose_admin(ctx, &stranger, &stranger.pubkey())
+        .await
+        .is_err());
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // A cancelled proposal can't be accepted
+    market
+        .propose_admin(ctx, &admin, &stranger.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(
+        market.get_settings(ctx).await.pending_admin,
+        Some(stranger.pubkey())
+    );
+    market.cancel_admin_transfer(ctx, &admin).await.unwrap();
+    assert_eq!(market.get_settings(ctx).await.pending_admin, None);
+    assert!(market.accept_admin(ctx, &stranger).await.is_err());
+
+    ////////////////////////////
+    ////////////////////////////
+
+    market
+        .propose_admin(ctx, &admin, &new_admin.pubkey())
+        .await
+        .unwrap();
+
+    // The old admin stays in charge until the proposal is accepted
+    assert!(market.accept_admin(ctx, &stranger).await.is_err());
+    market.update(ctx, &admin, 3, 4).await.unwrap();
+    market
+        .update_quote_signer(ctx, &admin, Some([1; ETH_ADDRESS_BYTES]))
+        .await
+        .unwrap();
+
+    // The old admin's quote key is removed with the transfer
+    market.accept_admin(ctx, &new_admin).await.unwrap();
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.admin, new_admin.pubkey());
+    assert_eq!(settings.pending_admin, None);
+    assert_eq!(settings.quote_signer, None);
+
+    assert!(market.update(ctx, &admin, 5, 6).await.is_err());
+    market.update(ctx, &new_admin, 5, 6).await.unwrap();
+
+    // The roles the old admin kept moved to the new admin
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.price_authority, new_admin.pubkey());
+    assert_eq!(settings.treasury_authority, new_admin.pubkey());
+
+    market.add_lamports(ctx, 1_000_000).await;
+    let destination = Keypair::new().pubkey();
+    assert!(market
+        .withdraw_lamports(ctx, &admin, &destination, 1000)
+        .await
+        .is_err());
+    market
+        .withdraw_lamports(ctx, &new_admin, &destination, 1000)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn admin_multisig() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let first_signer = get_admin(ctx, 1_000_000_000).await;
+    let second_signer = get_admin(ctx, 1_000_000_000).await;
+    let third_signer = get_admin(ctx, 1_000_000_000).await;
+    let stranger = get_admin(ctx, 1_000_000_000).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 1, 2).await;
+
+    let multisig = AdminMultisig {
+        threshold: 2,
+        signers: vec![
+            first_signer.pubkey(),
+            second_signer.pubkey(),
+            third_signer.pubkey(),
+        ],
+    };
+
+    // The threshold can't exceed the number of signers
+    let invalid_multisig = AdminMultisig {
+        threshold: 4,
+        ..multisig.clone()
+    };
+    assert!(market
+        .set_admin_multisig(ctx, &[&admin], Some(invalid_multisig))
+        .await
+        .is_err());
+
+    market
+        .set_admin_multisig(ctx, &[&admin], Some(multisig))
+        .await
+        .unwrap();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The admin, a single signer or a repeated signer can't meet the threshold
+    assert!(market.set_paused(ctx, &admin, true).await.is_err());
+    assert!(market
+        .set_paused_with_signers(ctx, &[&first_signer], true)
+        .await
+        .is_err());
+    assert!(market
+        .set_paused_with_signers(ctx, &[&first_signer, &first_signer], true)
+        .await
+        .is_err());
+    assert!(market
+        .set_paused_with_signers(ctx, &[&first_signer, &stranger], true)
+        .await
+        .is_err());
+
+    market
+        .set_paused_with_signers(ctx, &[&first_signer, &third_signer], true)
+        .await
+        .unwrap();
+    assert!(market.get_settings(ctx).await.paused);
+
+    // The treasury the admin keeps is behind the multisig as well
+    market.add_lamports(ctx, 1_000_000).await;
+    let destination = Keypair::new().pubkey();
+    assert!(market
+        .withdraw_lamports(ctx, &admin, &destination, 1000)
+        .await
+        .is_err());
+    assert!(market
+        .withdraw_lamports(ctx, &first_signer, &destination, 1000)
+        .await
+        .is_err());
+    market
+        .withdraw_lamports_with_signers(ctx, &[&first_signer, &second_signer], &destination, 1000)
+        .await
+        .unwrap();
+    assert_eq!(market.get_balance(ctx).await, 1_000_000 - 1000);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    market
+        .set_admin_multisig(ctx, &[&second_signer, &third_signer], None)
+        .await
+        .unwrap();
+    assert!(market.get_settings(ctx).await.admin_multisig.is_none());
+    market.set_paused(ctx, &admin, false).await.unwrap();
+}
+
+#[tokio::test]
+async fn market_roles() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let price_authority = get_admin(ctx, 1_000_000_000).await;
+    let treasury_authority = get_admin(ctx, 1_000_000_000).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 1, 2).await;
+    market.add_lamports(ctx, 1_000_000).await;
+
+    // The admin holds every role until they delegate them
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.price_authority, admin.pubkey());
+    assert_eq!(settings.treasury_authority, admin.pubkey());
+
+    // Only the owner assigns the roles
+    assert!(market
+        .update_roles(
+            ctx,
+            &price_authority,
+            &price_authority.pubkey(),
+            &price_authority.pubkey()
+        )
+        .await
+        .is_err());
+    market
+        .update_roles(
+            ctx,
+            &admin,
+            &price_authority.pubkey(),
+            &treasury_authority.pubkey(),
+        )
+        .await
+        .unwrap();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Each role is limited to its own instructions
+    assert!(market.update(ctx, &admin, 3, 4).await.is_err());
+    assert!(market.update(ctx, &treasury_authority, 3, 4).await.is_err());
+    market.update(ctx, &price_authority, 3, 4).await.unwrap();
+
+    let destination = Keypair::new().pubkey();
+    assert!(market
+        .withdraw_lamports(ctx, &admin, &destination, 1000)
+        .await
+        .is_err());
+    assert!(market
+        .withdraw_lamports(ctx, &price_authority, &destination, 1000)
+        .await
+        .is_err());
+    market
+        .withdraw_lamports(ctx, &treasury_authority, &destination, 1000)
+        .await
+        .unwrap();
+    assert_eq!(market.get_balance(ctx).await, 1_000_000 - 1000);
+
+    // The owner keeps the rest of the administration
+    assert!(market
+        .set_paused(ctx, &price_authority, true)
+        .await
+        .is_err());
+    market.set_paused(ctx, &admin, true).await.unwrap();
+}
+
+#[tokio::test]
+async fn price_band() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let price_authority = get_admin(ctx, 1_000_000_000).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 100, 200).await;
+    market
+        .update_roles(ctx, &admin, &price_authority.pubkey(), &admin.pubkey())
+        .await
+        .unwrap();
+
+    let price_band = PriceBand {
+        max_change_basis_points: 1000,
+        min_update_interval: 0,
+    };
+
+    // Only the owner sets the band
+    assert!(market
+        .update_price_band(ctx, &price_authority, Some(price_band))
+        .await
+        .is_err());
+    market
+        .update_price_band(ctx, &admin, Some(price_band))
+        .await
+        .unwrap();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Each price moves at most 10%, a typo is rejected
+    assert!(market
+        .update(ctx, &price_authority, 100, 2000)
+        .await
+        .is_err());
+    assert!(market.update(ctx, &price_authority, 89, 200).await.is_err());
+    market.update(ctx, &price_authority, 90, 220).await.unwrap();
+
+    // Prices are compared per whole token
+    assert!(market
+        .update_with_denominator(ctx, &price_authority, 180, 440, 1)
+        .await
+        .is_err());
     market
-        .initialize(ctx, &admin, &mint.pubkey(), 120, 0, 0)
-        .await;
+        .update_with_denominator(ctx, &price_authority, 180, 440, 2)
+        .await
+        .unwrap();
 
+    ////////////////////////////
+    ////////////////////////////
+
+    let price_band = PriceBand {
+        min_update_interval: 1_000_000,
+        ..price_band
+    };
     market
-        .initialize(ctx, &admin, &mint.pubkey(), 140, 0, 0)
-        .await;
+        .update_price_band(ctx, &admin, Some(price_band))
+        .await
+        .unwrap();
+
+    // The last update was just now
+    assert!(market
+        .update_with_denominator(ctx, &price_authority, 190, 450, 2)
+        .await
+        .is_err());
+
+    market.update_price_band(ctx, &admin, None).await.unwrap();
+    market.update(ctx, &price_authority, 1, 2000).await.unwrap();
+}
+
+#[tokio::test]
+#[should_panic]
+async fn market_initialized_with_negative_spread() {
+    let ctx = &mut get_context().await;
+    let admin = get_admin(ctx, 100_000_000).await;
+    let mint = generate_mint(ctx).await;
+
+    TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 101, 100).await;
+}
+
+#[tokio::test]
+async fn price_spread() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let signers = &[&admin];
+
+    // The minimum spread is at most the whole buy price and holds from the start
+    let args = InitializeArgs {
+        min_spread_basis_points: 10001,
+        ..TestMarket::initialize_args(&admin.pubkey(), 0, 200)
+    };
+    let initialize = MarketInstructions::initialize_store(&admin.pubkey(), &mint.pubkey(), args);
+    assert_market_error(
+        process_instructions(ctx, &[initialize], signers).await,
+        MarketError::InvalidSpread,
+    );
+    let args = InitializeArgs {
+        min_spread_basis_points: 500,
+        ..TestMarket::initialize_args(&admin.pubkey(), 191, 200)
+    };
+    let initialize = MarketInstructions::initialize_store(&admin.pubkey(), &mint.pubkey(), args);
+    assert_market_error(
+        process_instructions(ctx, &[initialize], signers).await,
+        MarketError::InvalidSpread,
+    );
+
+    let args = InitializeArgs {
+        min_spread_basis_points: 500,
+        ..TestMarket::initialize_args(&admin.pubkey(), 190, 200)
+    };
+    let market = TestMarket::new_with_args(ctx, &admin, &mint.pubkey(), 100, args).await;
+    assert_eq!(market.get_settings(ctx).await.min_spread_basis_points, 500);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The sell price never exceeds the buy price
+    assert_market_error(
+        market.update(ctx, &admin, 201, 200).await,
+        MarketError::InvalidSpread,
+    );
+
+    // At least 5% of the buy price in between, without a price band
+    assert_market_error(
+        market.update(ctx, &admin, 381, 400).await,
+        MarketError::InvalidSpread,
+    );
+    market.update(ctx, &admin, 380, 400).await.unwrap();
 }
@@ -1,28 +1,100 @@
+use borsh::BorshSerialize;
 use helpers::{
     client::TestClient,
-    common::{generate_mint, get_admin, get_context},
+    common::{
+        assert_market_error, foreign_settings, generate_mint, generate_mint_with_decimals,
+        get_admin, get_context, get_context_with_accounts, process_instructions,
+        process_with_account, transfer_lamports,
+    },
     market::TestMarket,
 };
+use libsecp256k1::{Message, PublicKey, SecretKey};
+use solana_program::{
+    instruction::{Instruction, InstructionError},
+    keccak,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
 use solana_program_test::{tokio, ProgramTestContext};
 use solana_sdk::{
-    signature::Keypair, signer::Signer, transaction::Transaction, transport::TransportError,
+    account::Account,
+    signature::Keypair,
+    signer::Signer,
+    transaction::{Transaction, TransactionError},
+    transport::TransportError,
+};
+use tokenmarket::{
+    allowlist::{AllowlistEntry, AllowlistTree},
+    error::MarketError,
+    instruction::{
+        FeeArgs, InitializeArgs, MarketInstructions, PriceArgs, SignedQuote, TokensNumber,
+    },
+    oracle::OraclePrice,
+    secp256k1::{self, ETH_ADDRESS_BYTES},
+    state::{BuyerRecord, MarketSettings, PricingMode, SaleLimits},
 };
-use tokenmarket::instruction::{MarketInstructions, TokensNumber};
 
 mod helpers;
 
 async fn buy_sell_common(
     ctx: &mut ProgramTestContext,
+    market: &TestMarket,
     client: &TestClient,
     instruction: MarketInstructions,
 ) -> Result<(), TransportError> {
+    let client_pubkey = client.client.pubkey();
+    let client_token_pubkey = client.client_token.pubkey();
+    let client_quote_pubkey = client.client_quote.as_ref().map(|quote| quote.pubkey());
+    let client_quote_pubkey = client_quote_pubkey.as_ref();
+    let settings = market.get_settings(ctx).await;
     let ix = match instruction {
-        MarketInstructions::Buy(args) => {
-            MarketInstructions::buy(&client.client.pubkey(), &client.client_token.pubkey(), args)
-        }
-        MarketInstructions::Sell(args) => {
-            MarketInstructions::sell(&client.client.pubkey(), &client.client_token.pubkey(), args)
-        }
+        MarketInstructions::Buy(args) => MarketInstructions::buy(
+            &client_pubkey,
+            &client_token_pubkey,
+            client_quote_pubkey,
+            &settings,
+            args,
+        ),
+        MarketInstructions::Sell(args) => MarketInstructions::sell(
+            &client_pubkey,
+            &client_token_pubkey,
+            client_quote_pubkey,
+            &settings,
+            args,
+        ),
+        MarketInstructions::BuyWithLimit {
+            tokens,
+            max_lamports,
+        } => MarketInstructions::buy_with_limit(
+            &client_pubkey,
+            &client_token_pubkey,
+            client_quote_pubkey,
+            &settings,
+            tokens,
+            max_lamports,
+        ),
+        MarketInstructions::SellWithLimit {
+            tokens,
+            min_lamports,
+        } => MarketInstructions::sell_with_limit(
+            &client_pubkey,
+            &client_token_pubkey,
+            client_quote_pubkey,
+            &settings,
+            tokens,
+            min_lamports,
+        ),
+        MarketInstructions::BuyExactLamports {
+            lamports,
+            min_tokens,
+        } => MarketInstructions::buy_exact_lamports(
+            &client_pubkey,
+            &client_token_pubkey,
+            client_quote_pubkey,
+            &settings,
+            lamports,
+            min_tokens,
+        ),
         _ => panic!("This instruction is covered in another test"),
     };
 
@@ -39,20 +111,64 @@ async fn buy_sell_common(
 
 async fn buy_tokens(
     ctx: &mut ProgramTestContext,
+    market: &TestMarket,
     tokens: u64,
     client: &TestClient,
 ) -> Result<(), TransportError> {
     let ix = MarketInstructions::Buy(TokensNumber(tokens));
-    buy_sell_common(ctx, client, ix).await
+    buy_sell_common(ctx, market, client, ix).await
 }
 
 async fn sell_tokens(
     ctx: &mut ProgramTestContext,
+    market: &TestMarket,
     tokens: u64,
     client: &TestClient,
 ) -> Result<(), TransportError> {
     let ix = MarketInstructions::Sell(TokensNumber(tokens));
-    buy_sell_common(ctx, client, ix).await
+    buy_sell_common(ctx, market, client, ix).await
+}
+
+async fn buy_tokens_with_limit(
+    ctx: &mut ProgramTestContext,
+    market: &TestMarket,
+    tokens: u64,
+    max_lamports: u64,
+    client: &TestClient,
+) -> Result<(), TransportError> {
+    let ix = MarketInstructions::BuyWithLimit {
+        tokens,
+        max_lamports,
+    };
+    buy_sell_common(ctx, market, client, ix).await
+}
+
+async fn sell_tokens_with_limit(
+    ctx: &mut ProgramTestContext,
+    market: &TestMarket,
+    tokens: u64,
+    min_lamports: u64,
+    client: &TestClient,
+) -> Result<(), TransportError> {
+    let ix = MarketInstructions::SellWithLimit {
+        tokens,
+        min_lamports,
+    };
+    buy_sell_common(ctx, market, client, ix).await
+}
+
+async fn buy_tokens_for_lamports(
+    ctx: &mut ProgramTestContext,
+    market: &TestMarket,
+    lamports: u64,
+    min_tokens: u64,
+    client: &TestClient,
+) -> Result<(), TransportError> {
+    let ix = MarketInstructions::BuyExactLamports {
+        lamports,
+        min_tokens,
+    };
+    buy_sell_common(ctx, market, client, ix).await
 }
 
 #[tokio::test]
@@ -84,7 +200,7 @@ async fn basic_transactions() {
 
     let tokens = 10;
     let lamports = tokens * buy_price;
-    buy_tokens(ctx, tokens, &client).await.unwrap();
+    buy_tokens(ctx, &market, tokens, &client).await.unwrap();
 
     market_tokens -= tokens;
     client_tokens += tokens;
@@ -102,7 +218,7 @@ async fn basic_transactions() {
 
     let tokens = 10;
     let lamports = tokens * sell_price;
-    sell_tokens(ctx, tokens, &client).await.unwrap();
+    sell_tokens(ctx, &market, tokens, &client).await.unwrap();
 
     market_tokens += tokens;
     client_tokens -= tokens;
@@ -120,20 +236,20 @@ async fn basic_transactions() {
 
     // InsufficientFunds
     let tokens = client_lamports / buy_price + 1;
-    assert!(buy_tokens(ctx, tokens, &client).await.is_err());
+    assert!(buy_tokens(ctx, &market, tokens, &client).await.is_err());
 
     ////////////////////////////
     ////////////////////////////
 
     // InsufficientFunds
     let tokens = market_lamports / sell_price + 1;
-    assert!(sell_tokens(ctx, tokens, &client).await.is_err());
+    assert!(sell_tokens(ctx, &market, tokens, &client).await.is_err());
 
     ////////////////////////////
     ////////////////////////////
 
     // Transfer 0 tokens is an error
-    assert!(sell_tokens(ctx, 0, &client).await.is_err());
+    assert!(sell_tokens(ctx, &market, 0, &client).await.is_err());
 
     ////////////////////////////
     ////////////////////////////
@@ -149,14 +265,14 @@ async fn basic_transactions() {
 
     // InsufficientTokens
     let tokens = client_tokens + 1;
-    assert!(sell_tokens(ctx, tokens, &client).await.is_err());
+    assert!(sell_tokens(ctx, &market, tokens, &client).await.is_err());
 
     ////////////////////////////
     ////////////////////////////
 
     // InsufficientTokens
     let tokens = market_tokens + 1;
-    assert!(buy_tokens(ctx, tokens, &client).await.is_err());
+    assert!(buy_tokens(ctx, &market, tokens, &client).await.is_err());
 
     ////////////////////////////
     ////////////////////////////
@@ -194,22 +310,1881 @@ async fn complex_transactions() {
     ////////////////////////////
 
     let another_client = TestClient::new(ctx, &another_mint.pubkey(), lamports, tokens).await;
-    assert!(buy_tokens(ctx, 1, &another_client).await.is_err());
-    assert!(sell_tokens(ctx, 1, &another_client).await.is_err());
+    assert!(buy_tokens(ctx, &market, 1, &another_client).await.is_err());
+    assert!(sell_tokens(ctx, &market, 1, &another_client).await.is_err());
 
     ////////////////////////////
     ////////////////////////////
 
     let mut strange_client = TestClient::new(ctx, &my_mint.pubkey(), lamports, tokens).await;
     strange_client.client = Keypair::new();
-    assert!(buy_tokens(ctx, 1, &strange_client).await.is_err());
-    assert!(sell_tokens(ctx, 1, &strange_client).await.is_err());
+    assert!(buy_tokens(ctx, &market, 1, &strange_client).await.is_err());
+    assert!(sell_tokens(ctx, &market, 1, &strange_client).await.is_err());
 
     ////////////////////////////
     ////////////////////////////
 
     let mut strange_client = TestClient::new(ctx, &my_mint.pubkey(), lamports, tokens).await;
     strange_client.client_token = Keypair::new();
-    assert!(buy_tokens(ctx, 1, &strange_client).await.is_err());
-    assert!(sell_tokens(ctx, 1, &strange_client).await.is_err());
+    assert!(buy_tokens(ctx, &market, 1, &strange_client).await.is_err());
+    assert!(sell_tokens(ctx, &market, 1, &strange_client).await.is_err());
+}
+
+#[tokio::test]
+async fn limited_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let client_lamports = 100000;
+    let client_tokens = 100;
+    let market_lamports = 100000;
+    let market_tokens = 100;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), client_lamports, client_tokens).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), market_tokens, 50, 100).await;
+    market.add_lamports(ctx, market_lamports).await;
+
+    // The price changes before the client's transactions land
+    let sell_price = 40;
+    let buy_price = 150;
+    market
+        .update(ctx, &admin, sell_price, buy_price)
+        .await
+        .unwrap();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    let tokens = 10;
+    assert!(
+        buy_tokens_with_limit(ctx, &market, tokens, tokens * 100, &client)
+            .await
+            .is_err()
+    );
+    assert!(
+        sell_tokens_with_limit(ctx, &market, tokens, tokens * 50, &client)
+            .await
+            .is_err()
+    );
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    buy_tokens_with_limit(ctx, &market, tokens, tokens * buy_price, &client)
+        .await
+        .unwrap();
+    sell_tokens_with_limit(ctx, &market, tokens, tokens * sell_price, &client)
+        .await
+        .unwrap();
+
+    let spent = tokens * (buy_price - sell_price);
+    assert_eq!(client.get_balance(ctx).await, client_lamports - spent);
+    assert_eq!(market.get_balance(ctx).await, market_lamports + spent);
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+}
+
+#[tokio::test]
+async fn exact_lamports_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let mut client_lamports = 10000;
+    let mut client_tokens = 0;
+    let mut market_lamports = 0;
+    let mut market_tokens = 100;
+    let buy_price = 30;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), client_lamports, client_tokens).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), market_tokens, 10, buy_price).await;
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // 100 lamports buy 3 tokens, 10 lamports stay with the client
+    buy_tokens_for_lamports(ctx, &market, 100, 3, &client)
+        .await
+        .unwrap();
+
+    client_tokens += 3;
+    market_tokens -= 3;
+    client_lamports -= 90;
+    market_lamports += 90;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Exact multiple of the price leaves no change
+    buy_tokens_for_lamports(ctx, &market, 120, 0, &client)
+        .await
+        .unwrap();
+
+    client_tokens += 4;
+    market_tokens -= 4;
+    client_lamports -= 120;
+    market_lamports += 120;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Not enough lamports for a single token
+    assert!(
+        buy_tokens_for_lamports(ctx, &market, buy_price - 1, 0, &client)
+            .await
+            .is_err()
+    );
+
+    // 119 lamports are rounded down to 3 tokens
+    assert!(buy_tokens_for_lamports(ctx, &market, 119, 4, &client)
+        .await
+        .is_err());
+
+    // InsufficientFunds
+    assert!(
+        buy_tokens_for_lamports(ctx, &market, client_lamports + buy_price, 0, &client)
+            .await
+            .is_err()
+    );
+
+    // InsufficientTokens
+    assert!(
+        buy_tokens_for_lamports(ctx, &market, (market_tokens + 1) * buy_price, 0, &client)
+            .await
+            .is_err()
+    );
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+}
+
+#[tokio::test]
+async fn fee_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let fee_recipient = Keypair::new().pubkey();
+
+    let mut client_lamports = 100000;
+    let mut client_tokens = 100;
+    let mut market_lamports = 100000;
+    let mut market_tokens = 100;
+    let mut fees = 0;
+    let sell_price = 50;
+    let buy_price = 100;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), client_lamports, client_tokens).await;
+    let market = TestMarket::new(
+        ctx,
+        &admin,
+        &mint.pubkey(),
+        market_tokens,
+        sell_price,
+        buy_price,
+    )
+    .await;
+    market.add_lamports(ctx, market_lamports).await;
+
+    // 1% fee
+    market
+        .update_fee(ctx, &admin, 100, &fee_recipient)
+        .await
+        .unwrap();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The fee is paid on top of the price
+    buy_tokens(ctx, &market, 10, &client).await.unwrap();
+
+    client_tokens += 10;
+    market_tokens -= 10;
+    client_lamports -= 1000 + 10;
+    market_lamports += 1000;
+    fees += 10;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(
+        ctx.banks_client.get_balance(fee_recipient).await.unwrap(),
+        fees
+    );
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The fee is taken from the sale proceeds and rounded up
+    sell_tokens(ctx, &market, 1, &client).await.unwrap();
+
+    client_tokens -= 1;
+    market_tokens += 1;
+    client_lamports += 50 - 1;
+    market_lamports -= 50;
+    fees += 1;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(
+        ctx.banks_client.get_balance(fee_recipient).await.unwrap(),
+        fees
+    );
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // 1000 lamports can't pay for 10 tokens with the fee, so only 9 are bought
+    buy_tokens_for_lamports(ctx, &market, 1000, 9, &client)
+        .await
+        .unwrap();
+
+    client_tokens += 9;
+    market_tokens -= 9;
+    client_lamports -= 900 + 9;
+    market_lamports += 900;
+    fees += 9;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(
+        ctx.banks_client.get_balance(fee_recipient).await.unwrap(),
+        fees
+    );
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Limits include the fee
+    assert!(buy_tokens_with_limit(ctx, &market, 10, 1000, &client)
+        .await
+        .is_err());
+    assert!(sell_tokens_with_limit(ctx, &market, 10, 500, &client)
+        .await
+        .is_err());
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The fee can't exceed 100% and only the admin can change it
+    assert!(market
+        .update_fee(ctx, &admin, 10001, &fee_recipient)
+        .await
+        .is_err());
+    let stranger = get_admin(ctx, 1_000_000_000).await;
+    assert!(market
+        .update_fee(ctx, &stranger, 0, &fee_recipient)
+        .await
+        .is_err());
+    assert_eq!(market.get_settings(ctx).await.fee_basis_points, 100);
+}
+
+#[tokio::test]
+async fn paused_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let destination = Keypair::new().pubkey();
+
+    let client_lamports = 10000;
+    let client_tokens = 10;
+    let market_lamports = 10000;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), client_lamports, client_tokens).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 10, 20).await;
+    market.add_lamports(ctx, market_lamports).await;
+
+    // Only the admin can pause the market
+    let stranger = get_admin(ctx, 1_000_000_000).await;
+    assert!(market.set_paused(ctx, &stranger, true).await.is_err());
+    market.set_paused(ctx, &admin, true).await.unwrap();
+    assert!(market.get_settings(ctx).await.paused);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    assert!(buy_tokens(ctx, &market, 1, &client).await.is_err());
+    assert!(sell_tokens(ctx, &market, 1, &client).await.is_err());
+    assert!(buy_tokens_for_lamports(ctx, &market, 100, 1, &client)
+        .await
+        .is_err());
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+
+    // The admin can still withdraw
+    market
+        .withdraw_lamports(ctx, &admin, &destination, 1000)
+        .await
+        .unwrap();
+    assert_eq!(market.get_balance(ctx).await, market_lamports - 1000);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    market.set_paused(ctx, &admin, false).await.unwrap();
+    assert!(!market.get_settings(ctx).await.paused);
+
+    buy_tokens(ctx, &market, 2, &client).await.unwrap();
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens + 2);
+}
+
+#[tokio::test]
+async fn quote_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let quote_mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let fee_collector = TestClient::new(ctx, &quote_mint.pubkey(), 0, 0).await;
+    let treasury = TestClient::new(ctx, &quote_mint.pubkey(), 0, 0).await;
+
+    let client_lamports = 10000;
+    let mut client_quote = 10000;
+    let mut client_tokens = 10;
+    let mut market_quote = 0;
+    let mut market_tokens = 100;
+    let mut fees = 0;
+
+    let mut client = TestClient::new(ctx, &mint.pubkey(), client_lamports, client_tokens).await;
+    client
+        .add_quote_account(ctx, &quote_mint.pubkey(), client_quote)
+        .await;
+
+    // 1% fee paid in quote tokens
+    let args = InitializeArgs {
+        prices: PriceArgs {
+            sell_price: 50,
+            buy_price: 100,
+            price_denominator: 1,
+        },
+        fee: FeeArgs {
+            fee_basis_points: 100,
+            fee_recipient: fee_collector.client_token.pubkey(),
+        },
+        reserve_sells: false,
+        quote_mint: Some(quote_mint.pubkey()),
+        pricing: PricingMode::Fixed,
+        limits: SaleLimits::default(),
+        min_spread_basis_points: 0,
+    };
+    let market = TestMarket::new_with_args(ctx, &admin, &mint.pubkey(), market_tokens, args).await;
+
+    ////////////////////////////
+    ////////////////////////////
+
+    buy_tokens(ctx, &market, 10, &client).await.unwrap();
+
+    client_tokens += 10;
+    market_tokens -= 10;
+    client_quote -= 1000 + 10;
+    market_quote += 1000;
+    fees += 10;
+
+    assert_eq!(client.get_quote_tokens_number(ctx).await, client_quote);
+    assert_eq!(market.get_quote_tokens_number(ctx).await, market_quote);
+    assert_eq!(fee_collector.get_tokens_number(ctx).await, fees);
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    sell_tokens(ctx, &market, 5, &client).await.unwrap();
+
+    client_tokens -= 5;
+    market_tokens += 5;
+    client_quote += 250 - 3;
+    market_quote -= 250;
+    fees += 3;
+
+    assert_eq!(client.get_quote_tokens_number(ctx).await, client_quote);
+    assert_eq!(market.get_quote_tokens_number(ctx).await, market_quote);
+    assert_eq!(fee_collector.get_tokens_number(ctx).await, fees);
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+
+    // Lamports aren't used to pay
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, 0);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // A client without a quote token account can't trade
+    let lamports_client = TestClient::new(ctx, &mint.pubkey(), 100000, 10).await;
+    assert!(buy_tokens(ctx, &market, 1, &lamports_client).await.is_err());
+    assert!(sell_tokens(ctx, &market, 1, &lamports_client)
+        .await
+        .is_err());
+
+    ////////////////////////////
+    ////////////////////////////
+
+    let treasury_pubkey = treasury.client_token.pubkey();
+    assert!(market
+        .withdraw_lamports(ctx, &admin, &treasury_pubkey, market_quote + 1)
+        .await
+        .is_err());
+    market
+        .withdraw_lamports(ctx, &admin, &treasury_pubkey, 100)
+        .await
+        .unwrap();
+
+    market_quote -= 100;
+    assert_eq!(market.get_quote_tokens_number(ctx).await, market_quote);
+    assert_eq!(treasury.get_tokens_number(ctx).await, 100);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    let quote_vault = MarketSettings::quote_vault_pubkey_with_bump(&mint.pubkey()).0;
+    market
+        .close_with_quote(
+            ctx,
+            &admin,
+            &client.client_token.pubkey(),
+            &Keypair::new().pubkey(),
+            Some(&treasury_pubkey),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(treasury.get_tokens_number(ctx).await, 100 + market_quote);
+    assert_eq!(
+        client.get_tokens_number(ctx).await,
+        client_tokens + market_tokens
+    );
+    assert!(ctx
+        .banks_client
+        .get_account(quote_vault)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn fractional_price_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint_with_decimals(ctx, 6).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let whole_token = 1_000_000;
+
+    let mut client_lamports = 10000;
+    let mut client_tokens = 10 * whole_token;
+    let mut market_lamports = 10000;
+    let mut market_tokens = 100 * whole_token;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), client_lamports, client_tokens).await;
+
+    // 1.5 lamports per whole token to buy, 0.5 to sell
+    let args = InitializeArgs {
+        prices: PriceArgs {
+            sell_price: 1,
+            buy_price: 3,
+            price_denominator: 2,
+        },
+        fee: FeeArgs {
+            fee_basis_points: 0,
+            fee_recipient: admin.pubkey(),
+        },
+        reserve_sells: false,
+        quote_mint: None,
+        pricing: PricingMode::Fixed,
+        limits: SaleLimits::default(),
+        min_spread_basis_points: 0,
+    };
+    let market = TestMarket::new_with_args(ctx, &admin, &mint.pubkey(), market_tokens, args).await;
+    market.add_lamports(ctx, market_lamports).await;
+
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.decimals, 6);
+    assert_eq!(settings.price_denominator, 2);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Buys are rounded up
+    for (tokens, lamports) in [(whole_token, 2), (1, 1), (2 * whole_token, 3)] {
+        buy_tokens(ctx, &market, tokens, &client).await.unwrap();
+
+        client_tokens += tokens;
+        market_tokens -= tokens;
+        client_lamports -= lamports;
+        market_lamports += lamports;
+
+        assert_eq!(client.get_balance(ctx).await, client_lamports);
+        assert_eq!(market.get_balance(ctx).await, market_lamports);
+        assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+        assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+    }
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Sells are rounded down
+    for (tokens, lamports) in [(3 * whole_token, 1), (4 * whole_token, 2)] {
+        sell_tokens(ctx, &market, tokens, &client).await.unwrap();
+
+        client_tokens -= tokens;
+        market_tokens += tokens;
+        client_lamports += lamports;
+        market_lamports -= lamports;
+
+        assert_eq!(client.get_balance(ctx).await, client_lamports);
+        assert_eq!(market.get_balance(ctx).await, market_lamports);
+        assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+        assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+    }
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // 10 lamports pay for 6.666666 tokens
+    let tokens = 6_666_666;
+    buy_tokens_for_lamports(ctx, &market, 10, tokens, &client)
+        .await
+        .unwrap();
+
+    client_tokens += tokens;
+    market_tokens -= tokens;
+    client_lamports -= 10;
+    market_lamports += 10;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(client.get_tokens_number(ctx).await, client_tokens);
+    assert_eq!(market.get_tokens_number(ctx).await, market_tokens);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The denominator can't be zero
+    assert!(market
+        .update_with_denominator(ctx, &admin, 1, 3, 0)
+        .await
+        .is_err());
+}
+
+async fn new_curve_market(
+    ctx: &mut ProgramTestContext,
+    admin: &Keypair,
+    mint: &Pubkey,
+    tokens: u64,
+    pricing: PricingMode,
+) -> TestMarket {
+    let args = InitializeArgs {
+        prices: PriceArgs {
+            sell_price: 0,
+            buy_price: 0,
+            price_denominator: 1,
+        },
+        fee: FeeArgs {
+            fee_basis_points: 0,
+            fee_recipient: admin.pubkey(),
+        },
+        reserve_sells: false,
+        quote_mint: None,
+        pricing,
+        limits: SaleLimits::default(),
+        min_spread_basis_points: 0,
+    };
+    TestMarket::new_with_args(ctx, admin, mint, tokens, args).await
+}
+
+#[tokio::test]
+async fn linear_curve_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let mut client_lamports = 100000;
+    let mut market_lamports = 0;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), client_lamports, 10).await;
+    let pricing = PricingMode::Linear {
+        base_price: 10,
+        slope: 2,
+    };
+    let market = new_curve_market(ctx, &admin, &mint.pubkey(), 100, pricing).await;
+
+    // The market hasn't sold anything to buy back
+    assert!(sell_tokens(ctx, &market, 1, &client).await.is_err());
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // 10 + 12 + 14, then 16 + 18
+    for (tokens, lamports) in [(3, 36), (2, 34)] {
+        buy_tokens(ctx, &market, tokens, &client).await.unwrap();
+
+        client_lamports -= lamports;
+        market_lamports += lamports;
+
+        assert_eq!(client.get_balance(ctx).await, client_lamports);
+        assert_eq!(market.get_balance(ctx).await, market_lamports);
+    }
+    assert_eq!(market.get_settings(ctx).await.tokens_sold, 5);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Tokens are bought back for the price they were sold at
+    sell_tokens(ctx, &market, 2, &client).await.unwrap();
+
+    client_lamports += 34;
+    market_lamports -= 34;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(market.get_settings(ctx).await.tokens_sold, 3);
+
+    assert!(sell_tokens(ctx, &market, 4, &client).await.is_err());
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The next tokens cost 16 and 18, so 30 lamports buy one of them
+    buy_tokens_for_lamports(ctx, &market, 30, 1, &client)
+        .await
+        .unwrap();
+
+    client_lamports -= 16;
+    market_lamports += 16;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(market.get_settings(ctx).await.tokens_sold, 4);
+}
+
+#[tokio::test]
+async fn exponential_curve_round_trips() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint_with_decimals(ctx, 3).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), 100_000_000, 0).await;
+    let pricing = PricingMode::Exponential {
+        base_price: 1000,
+        growth_basis_points: 500,
+    };
+    let market = new_curve_market(ctx, &admin, &mint.pubkey(), 1_000_000, pricing).await;
+
+    // The first tokens stay sold, so round trips start at different points of the curve
+    buy_tokens(ctx, &market, 2500, &client).await.unwrap();
+
+    for tokens in [1, 7, 999, 1000, 1501, 12345] {
+        let market_lamports = market.get_balance(ctx).await;
+
+        buy_tokens(ctx, &market, tokens, &client).await.unwrap();
+        assert!(market.get_balance(ctx).await > market_lamports);
+
+        sell_tokens(ctx, &market, tokens, &client).await.unwrap();
+        assert!(market.get_balance(ctx).await >= market_lamports);
+        assert_eq!(market.get_settings(ctx).await.tokens_sold, 2500);
+    }
+}
+
+#[tokio::test]
+async fn exponential_curve_large_budget() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint_with_decimals(ctx, 3).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), 100_000_000, 0).await;
+    let pricing = PricingMode::Exponential {
+        base_price: 1000,
+        growth_basis_points: 500,
+    };
+    let market = new_curve_market(ctx, &admin, &mint.pubkey(), 50_000, pricing).await;
+
+    // The budget pays for every token of the market, only what they cost is taken
+    buy_tokens_for_lamports(ctx, &market, u64::MAX, 1, &client)
+        .await
+        .unwrap();
+
+    assert_eq!(market.get_tokens_number(ctx).await, 0);
+    assert_eq!(client.get_tokens_number(ctx).await, 50_000);
+    assert_eq!(market.get_settings(ctx).await.tokens_sold, 50_000);
+    assert!(client.get_balance(ctx).await > 0);
+}
+
+#[test]
+fn exponential_curve_total_is_monotonic() {
+    for growth_basis_points in [0, 1, 37, 500, 10000] {
+        let pricing = PricingMode::Exponential {
+            base_price: 1000,
+            growth_basis_points,
+        };
+
+        for decimals in [0, 3, 9] {
+            let unit = 10u64.pow(decimals.into());
+            let mut previous = 0;
+
+            // Crosses every whole-token boundary of the first tokens, where the partial price switches
+            for whole_tokens in 0..20 {
+                for rest in [0, 1, unit / 2, unit.saturating_sub(1)] {
+                    let tokens = whole_tokens * unit + rest;
+                    let total = pricing.curve_total(tokens, decimals).unwrap();
+                    assert!(total >= previous);
+                    previous = total;
+                }
+            }
+        }
+    }
+}
+
+/// Product of the market's lamports and token reserves
+async fn reserves_product(ctx: &mut ProgramTestContext, market: &TestMarket) -> u128 {
+    u128::from(market.get_balance(ctx).await) * u128::from(market.get_tokens_number(ctx).await)
+}
+
+#[tokio::test]
+async fn constant_product_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let fee_recipient = Keypair::new().pubkey();
+
+    let client = TestClient::new(ctx, &mint.pubkey(), 1_000_000, 100).await;
+    let market = new_curve_market(
+        ctx,
+        &admin,
+        &mint.pubkey(),
+        1000,
+        PricingMode::ConstantProduct,
+    )
+    .await;
+    market
+        .update_fee(ctx, &admin, 100, &fee_recipient)
+        .await
+        .unwrap();
+
+    // There are no lamports to price the tokens against
+    assert!(buy_tokens(ctx, &market, 1, &client).await.is_err());
+
+    market.add_lamports(ctx, 100_000).await;
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // 100000 * 100 / 900 rounded up, with a fee of 112
+    buy_tokens(ctx, &market, 100, &client).await.unwrap();
+
+    assert_eq!(client.get_balance(ctx).await, 1_000_000 - 11224);
+    assert_eq!(client.get_tokens_number(ctx).await, 200);
+    assert_eq!(market.get_balance(ctx).await, 111_112);
+    assert_eq!(market.get_tokens_number(ctx).await, 900);
+    assert_eq!(
+        ctx.banks_client.get_balance(fee_recipient).await.unwrap(),
+        112
+    );
+
+    // The market can't be drained of its tokens
+    assert!(buy_tokens(ctx, &market, 900, &client).await.is_err());
+
+    // The next 100 tokens cost 13889 with a fee of 139
+    assert!(buy_tokens_with_limit(ctx, &market, 100, 14027, &client)
+        .await
+        .is_err());
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // 111112 * 100 / 1000 rounded down, the fee of 112 is taken out of it
+    assert!(sell_tokens_with_limit(ctx, &market, 100, 11000, &client)
+        .await
+        .is_err());
+    sell_tokens_with_limit(ctx, &market, 100, 10999, &client)
+        .await
+        .unwrap();
+
+    assert_eq!(client.get_balance(ctx).await, 1_000_000 - 11224 + 10999);
+    assert_eq!(client.get_tokens_number(ctx).await, 100);
+    assert_eq!(market.get_balance(ctx).await, 100_001);
+    assert_eq!(market.get_tokens_number(ctx).await, 1000);
+    assert_eq!(
+        ctx.banks_client.get_balance(fee_recipient).await.unwrap(),
+        224
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // 9900 lamports of price with a fee of 99 buy 1000 * 9900 / 109901 tokens
+    assert!(buy_tokens_for_lamports(ctx, &market, 10000, 91, &client)
+        .await
+        .is_err());
+    buy_tokens_for_lamports(ctx, &market, 10000, 90, &client)
+        .await
+        .unwrap();
+
+    assert_eq!(client.get_tokens_number(ctx).await, 190);
+    assert_eq!(market.get_tokens_number(ctx).await, 910);
+}
+
+#[tokio::test]
+async fn constant_product_invariant() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint_with_decimals(ctx, 3).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), 100_000_000, 50_000).await;
+    let market = new_curve_market(
+        ctx,
+        &admin,
+        &mint.pubkey(),
+        1_000_000,
+        PricingMode::ConstantProduct,
+    )
+    .await;
+    market.add_lamports(ctx, 7_777_777).await;
+
+    let mut product = reserves_product(ctx, &market).await;
+
+    for tokens in [1, 3, 999, 12345, 7, 50_001, 333_333] {
+        buy_tokens(ctx, &market, tokens, &client).await.unwrap();
+
+        let next_product = reserves_product(ctx, &market).await;
+        assert!(next_product >= product);
+        product = next_product;
+
+        sell_tokens(ctx, &market, tokens / 2 + 1, &client)
+            .await
+            .unwrap();
+
+        let next_product = reserves_product(ctx, &market).await;
+        assert!(next_product >= product);
+        product = next_product;
+    }
+
+    // Too few lamports for a single token of the reserves
+    for lamports in [1, 10] {
+        assert!(matches!(
+            buy_tokens_for_lamports(ctx, &market, lamports, 0, &client).await,
+            Err(TransportError::TransactionError(
+                TransactionError::InstructionError(0, InstructionError::InvalidArgument)
+            ))
+        ));
+    }
+    assert_eq!(reserves_product(ctx, &market).await, product);
+
+    for lamports in [1234, 999_999] {
+        buy_tokens_for_lamports(ctx, &market, lamports, 1, &client)
+            .await
+            .unwrap();
+
+        let next_product = reserves_product(ctx, &market).await;
+        assert!(next_product >= product);
+        product = next_product;
+    }
+}
+
+/// Oracle account publishing `price` at `slot`
+fn oracle_account(price: u64, slot: u64) -> Account {
+    Account {
+        lamports: 1_000_000_000,
+        data: OraclePrice { price, slot }.try_to_vec().unwrap(),
+        owner: Pubkey::new_unique(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+#[tokio::test]
+async fn oracle_transactions() {
+    let oracle = Pubkey::new_unique();
+    let other_oracle = Pubkey::new_unique();
+    let ctx = &mut get_context_with_accounts(vec![
+        (oracle, oracle_account(1000, 0)),
+        (other_oracle, oracle_account(10, 0)),
+    ])
+    .await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let mut client_lamports = 1_000_000;
+    let mut market_lamports = 100_000;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), client_lamports, 10).await;
+    // 5% around the oracle price
+    let pricing = PricingMode::Oracle {
+        oracle,
+        spread_basis_points: 500,
+        max_staleness_slots: 100,
+    };
+    let market = new_curve_market(ctx, &admin, &mint.pubkey(), 100, pricing).await;
+    market.add_lamports(ctx, market_lamports).await;
+
+    ////////////////////////////
+    ////////////////////////////
+
+    buy_tokens(ctx, &market, 2, &client).await.unwrap();
+
+    client_lamports -= 2 * 1050;
+    market_lamports += 2 * 1050;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+
+    sell_tokens(ctx, &market, 3, &client).await.unwrap();
+
+    client_lamports += 3 * 950;
+    market_lamports -= 3 * 950;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+
+    buy_tokens_for_lamports(ctx, &market, 2099, 1, &client)
+        .await
+        .unwrap();
+    assert_eq!(client.get_tokens_number(ctx).await, 10);
+
+    // The last prices traded at are kept with the settings
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.buy_price, 1050);
+    assert_eq!(settings.sell_price, 950);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Only the oracle in the settings is trusted
+    let mut wrong_settings = market.get_settings(ctx).await;
+    wrong_settings.pricing = PricingMode::Oracle {
+        oracle: other_oracle,
+        spread_basis_points: 500,
+        max_staleness_slots: 100,
+    };
+    let ix = MarketInstructions::buy(
+        &client.client.pubkey(),
+        &client.client_token.pubkey(),
+        None,
+        &wrong_settings,
+        TokensNumber(1),
+    );
+    assert_market_error(
+        process_instructions(ctx, &[ix], &[&client.client]).await,
+        MarketError::OraclePubkeyMismatch,
+    );
+
+    // The price was published too long ago
+    ctx.warp_to_slot(500).unwrap();
+    assert_market_error(
+        buy_tokens(ctx, &market, 1, &client).await,
+        MarketError::StaleOraclePrice,
+    );
+    assert_market_error(
+        sell_tokens(ctx, &market, 1, &client).await,
+        MarketError::StaleOraclePrice,
+    );
+}
+
+/// Ethereum address of the quote key
+fn quote_signer(quote_key: &SecretKey) -> [u8; ETH_ADDRESS_BYTES] {
+    let pubkey = PublicKey::from_secret_key(quote_key).serialize();
+    secp256k1::eth_address(pubkey[1..].try_into().unwrap())
+}
+
+/// Verification of `quote_key`'s signature of `quote` for `client`,
+/// the first instruction of the transaction
+fn sign_quote(
+    quote_key: &SecretKey,
+    mint: &Pubkey,
+    client: &Pubkey,
+    quote: &SignedQuote,
+) -> Instruction {
+    let settings_pubkey = MarketSettings::settings_pubkey_with_bump(mint).0;
+    let message = quote.message(&settings_pubkey, client);
+    let hash = keccak::hash(&message);
+    let (signature, recovery_id) = libsecp256k1::sign(&Message::parse(&hash.0), quote_key);
+    secp256k1::new_secp256k1_instruction(
+        &quote_signer(quote_key),
+        &signature.serialize(),
+        recovery_id.serialize(),
+        &message,
+        0,
+    )
+}
+
+async fn buy_with_signed_quote(
+    ctx: &mut ProgramTestContext,
+    market: &TestMarket,
+    client: &TestClient,
+    tokens: u64,
+    quote: SignedQuote,
+    verification: Option<Instruction>,
+) -> Result<(), TransportError> {
+    let settings = market.get_settings(ctx).await;
+    let ix = MarketInstructions::buy_with_signed_quote(
+        &client.client.pubkey(),
+        &client.client_token.pubkey(),
+        None,
+        &settings,
+        tokens,
+        quote,
+    );
+    let ixs: Vec<_> = verification.into_iter().chain(Some(ix)).collect();
+
+    let blockhash = ctx.banks_client.get_recent_blockhash().await.unwrap();
+    ctx.banks_client
+        .process_transaction(Transaction::new_signed_with_payer(
+            &ixs,
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer, &client.client],
+            blockhash,
+        ))
+        .await
+}
+
+#[tokio::test]
+async fn signed_quote_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let quote_key = SecretKey::parse(&[1; 32]).unwrap();
+    let stranger_key = SecretKey::parse(&[2; 32]).unwrap();
+    let nonce_rent = ctx
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(0);
+
+    let client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 0).await;
+    let other_client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 0).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 50, 100).await;
+    let client_pubkey = client.client.pubkey();
+
+    let quote = SignedQuote {
+        price: 70,
+        max_tokens: 10,
+        expiry_slot: 1000,
+        nonce: 1,
+    };
+    let verification = sign_quote(&quote_key, &mint.pubkey(), &client_pubkey, &quote);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Quotes are disabled until the admin sets the quote key
+    assert_market_error(
+        buy_with_signed_quote(ctx, &market, &client, 5, quote, Some(verification.clone())).await,
+        MarketError::InvalidQuoteSignature,
+    );
+    market
+        .update_quote_signer(ctx, &admin, Some(quote_signer(&quote_key)))
+        .await
+        .unwrap();
+
+    // The quote can't be used without its verification or with changed terms
+    assert_market_error(
+        buy_with_signed_quote(ctx, &market, &client, 4, quote, None).await,
+        MarketError::InvalidQuoteSignature,
+    );
+    let changed_quote = SignedQuote { price: 1, ..quote };
+    assert_market_error(
+        buy_with_signed_quote(
+            ctx,
+            &market,
+            &client,
+            4,
+            changed_quote,
+            Some(verification.clone()),
+        )
+        .await,
+        MarketError::InvalidQuoteSignature,
+    );
+
+    // Only quotes signed with the quote key are accepted
+    let stranger_verification = sign_quote(&stranger_key, &mint.pubkey(), &client_pubkey, &quote);
+    assert_market_error(
+        buy_with_signed_quote(ctx, &market, &client, 4, quote, Some(stranger_verification)).await,
+        MarketError::InvalidQuoteSignature,
+    );
+
+    // The runtime rejects a verification claiming the quote key's signature without it,
+    // the address follows the instruction's offsets
+    let mut forged_verification = sign_quote(&stranger_key, &mint.pubkey(), &client_pubkey, &quote);
+    forged_verification.data[12..12 + ETH_ADDRESS_BYTES].copy_from_slice(&quote_signer(&quote_key));
+    assert!(matches!(
+        buy_with_signed_quote(ctx, &market, &client, 4, quote, Some(forged_verification)).await,
+        Err(TransportError::TransactionError(
+            TransactionError::InvalidAccountIndex
+        ))
+    ));
+
+    // The quote is only for the client it was signed for
+    assert_market_error(
+        buy_with_signed_quote(
+            ctx,
+            &market,
+            &other_client,
+            4,
+            quote,
+            Some(verification.clone()),
+        )
+        .await,
+        MarketError::InvalidQuoteSignature,
+    );
+
+    // The quote covers at most 10 tokens
+    assert_market_error(
+        buy_with_signed_quote(ctx, &market, &client, 11, quote, Some(verification.clone())).await,
+        MarketError::QuoteTokensExceeded,
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    buy_with_signed_quote(ctx, &market, &client, 4, quote, Some(verification.clone()))
+        .await
+        .unwrap();
+
+    assert_eq!(client.get_tokens_number(ctx).await, 4);
+    assert_eq!(
+        client.get_balance(ctx).await,
+        10_000_000 - 4 * 70 - nonce_rent
+    );
+    assert_eq!(market.get_balance(ctx).await, 4 * 70);
+
+    // The quote can't be replayed
+    assert_market_error(
+        buy_with_signed_quote(ctx, &market, &client, 3, quote, Some(verification)).await,
+        MarketError::QuoteNonceUsed,
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    let expiring_quote = SignedQuote {
+        expiry_slot: 100,
+        nonce: 2,
+        ..quote
+    };
+    let verification = sign_quote(&quote_key, &mint.pubkey(), &client_pubkey, &expiring_quote);
+
+    ctx.warp_to_slot(200).unwrap();
+    assert_market_error(
+        buy_with_signed_quote(ctx, &market, &client, 1, expiring_quote, Some(verification)).await,
+        MarketError::QuoteExpired,
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Lamports sent to the nonce's record beforehand don't use the quote up
+    let prefunded_quote = SignedQuote { nonce: 3, ..quote };
+    let verification = sign_quote(&quote_key, &mint.pubkey(), &client_pubkey, &prefunded_quote);
+    let nonce_pubkey = MarketSettings::nonce_pubkey_with_bump(&mint.pubkey(), 3).0;
+    transfer_lamports(ctx, &nonce_pubkey, 1).await;
+
+    buy_with_signed_quote(
+        ctx,
+        &market,
+        &client,
+        2,
+        prefunded_quote,
+        Some(verification),
+    )
+    .await
+    .unwrap();
+    assert_eq!(client.get_tokens_number(ctx).await, 6);
+    assert_eq!(
+        client.get_balance(ctx).await,
+        10_000_000 - 6 * 70 - nonce_rent - (nonce_rent - 1)
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Removing the quote key disables quotes again
+    market.update_quote_signer(ctx, &admin, None).await.unwrap();
+
+    let late_quote = SignedQuote { nonce: 4, ..quote };
+    let verification = sign_quote(&quote_key, &mint.pubkey(), &client_pubkey, &late_quote);
+    assert_market_error(
+        buy_with_signed_quote(ctx, &market, &client, 1, late_quote, Some(verification)).await,
+        MarketError::InvalidQuoteSignature,
+    );
+    assert_eq!(client.get_tokens_number(ctx).await, 6);
+}
+
+#[tokio::test]
+async fn dutch_auction_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let mut client_lamports = 1_000_000;
+    // Rent exempt, so warping doesn't collect rent from the market
+    let mut market_lamports = 1_000_000;
+
+    let now = ctx.banks_client.get_clock().await.unwrap().unix_timestamp;
+    let (start_time, end_time) = (now + 1, now + 10_000);
+    let pricing = PricingMode::DutchAuction {
+        start_price: 1000,
+        end_price: 100,
+        start_time,
+        end_time,
+    };
+
+    let client = TestClient::new(ctx, &mint.pubkey(), client_lamports, 0).await;
+    let market = new_curve_market(ctx, &admin, &mint.pubkey(), 100, pricing).await;
+    market.update(ctx, &admin, 50, 1000).await.unwrap();
+    market.add_lamports(ctx, market_lamports).await;
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The auction hasn't started yet
+    buy_tokens(ctx, &market, 1, &client).await.unwrap();
+
+    client_lamports -= 1000;
+    market_lamports += 1000;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    ctx.warp_to_slot(2000).unwrap();
+    let now = ctx.banks_client.get_clock().await.unwrap().unix_timestamp;
+    assert!(start_time < now && now < end_time);
+
+    // The price decays linearly, the transaction may land a little later than the clock was read
+    let price_now = 1000 - (900 * (now - start_time) / (end_time - start_time)) as u64;
+    buy_tokens(ctx, &market, 2, &client).await.unwrap();
+
+    let price = market.get_settings(ctx).await.buy_price;
+    assert!(100 < price && price <= price_now && price_now < 1000);
+
+    client_lamports -= 2 * price;
+    market_lamports += 2 * price;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    ctx.warp_to_slot(100_000).unwrap();
+    let now = ctx.banks_client.get_clock().await.unwrap().unix_timestamp;
+    assert!(now >= end_time);
+
+    // The price stays at the floor after the auction, tokens are bought back for the sell price
+    buy_tokens(ctx, &market, 3, &client).await.unwrap();
+    sell_tokens(ctx, &market, 1, &client).await.unwrap();
+
+    client_lamports -= 3 * 100 - 50;
+    market_lamports += 3 * 100 - 50;
+
+    assert_eq!(client.get_balance(ctx).await, client_lamports);
+    assert_eq!(market.get_balance(ctx).await, market_lamports);
+    assert_eq!(market.get_settings(ctx).await.buy_price, 100);
+}
+
+#[tokio::test]
+async fn sale_limits_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let first_client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 0).await;
+    let second_client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 0).await;
+    let third_client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 0).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 5, 10).await;
+    // Rent exempt, so warping doesn't collect rent from the market
+    market.add_lamports(ctx, 1_000_000).await;
+
+    let now = ctx.banks_client.get_clock().await.unwrap().unix_timestamp;
+    let limits = SaleLimits {
+        opens_at: Some(now + 1_000_000),
+        closes_at: Some(now + 1000),
+        max_tokens_sold: Some(10),
+        max_tokens_per_buyer: Some(4),
+    };
+
+    // The sale has to open before it closes
+    assert!(market
+        .update_sale_limits(ctx, &admin, limits)
+        .await
+        .is_err());
+
+    let limits = SaleLimits {
+        closes_at: Some(now + 2_000_000),
+        ..limits
+    };
+    market
+        .update_sale_limits(ctx, &admin, limits)
+        .await
+        .unwrap();
+    assert!(buy_tokens(ctx, &market, 1, &first_client).await.is_err());
+
+    let limits = SaleLimits {
+        opens_at: Some(now - 1),
+        closes_at: Some(now + 1000),
+        ..limits
+    };
+    market
+        .update_sale_limits(ctx, &admin, limits)
+        .await
+        .unwrap();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Each client buys at most 4 tokens
+    buy_tokens(ctx, &market, 3, &first_client).await.unwrap();
+    assert!(buy_tokens(ctx, &market, 2, &first_client).await.is_err());
+    buy_tokens(ctx, &market, 1, &first_client).await.unwrap();
+    buy_tokens(ctx, &market, 4, &second_client).await.unwrap();
+
+    // The market sells at most 10 tokens
+    assert_market_error(
+        buy_tokens(ctx, &market, 3, &third_client).await,
+        MarketError::SaleCapExceeded,
+    );
+    buy_tokens(ctx, &market, 2, &third_client).await.unwrap();
+    assert_eq!(market.get_settings(ctx).await.tokens_sold, 10);
+
+    // Selling tokens back frees neither the market's cap nor the buyer's
+    sell_tokens(ctx, &market, 1, &first_client).await.unwrap();
+    assert!(buy_tokens(ctx, &market, 1, &first_client).await.is_err());
+    assert_market_error(
+        buy_tokens(ctx, &market, 1, &third_client).await,
+        MarketError::SaleCapExceeded,
+    );
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.tokens_sold, 9);
+    assert_eq!(settings.tokens_sold_total, 10);
+
+    let buyer_record_pubkey = MarketSettings::buyer_record_pubkey_with_bump(
+        &mint.pubkey(),
+        &first_client.client.pubkey(),
+    )
+    .0;
+    let buyer_record = ctx
+        .banks_client
+        .get_account(buyer_record_pubkey)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        BuyerRecord::from_account_data(&buyer_record.data)
+            .unwrap()
+            .tokens_bought,
+        4
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    ctx.warp_to_slot(100_000).unwrap();
+    let now = ctx.banks_client.get_clock().await.unwrap().unix_timestamp;
+    assert!(now >= limits.closes_at.unwrap());
+
+    // Tokens are still bought back after the sale
+    sell_tokens(ctx, &market, 1, &second_client).await.unwrap();
+    assert!(buy_tokens_with_limit(ctx, &market, 1, 10, &third_client)
+        .await
+        .is_err());
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.tokens_sold, 8);
+    assert_eq!(settings.tokens_sold_total, 10);
+}
+
+async fn buy_with_proof(
+    ctx: &mut ProgramTestContext,
+    market: &TestMarket,
+    client: &TestClient,
+    tokens: u64,
+    allocation: Option<u64>,
+    proof: Vec<[u8; 32]>,
+) -> Result<(), TransportError> {
+    let settings = market.get_settings(ctx).await;
+    let ix = MarketInstructions::buy_with_proof(
+        &client.client.pubkey(),
+        &client.client_token.pubkey(),
+        None,
+        &settings,
+        tokens,
+        allocation,
+        proof,
+    );
+
+    let blockhash = ctx.banks_client.get_recent_blockhash().await.unwrap();
+    ctx.banks_client
+        .process_transaction(Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer, &client.client],
+            blockhash,
+        ))
+        .await
+}
+
+#[tokio::test]
+async fn allowlist_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let first_client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 0).await;
+    let second_client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 0).await;
+    let stranger = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 0).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 5, 10).await;
+
+    let entries = [
+        AllowlistEntry {
+            wallet: first_client.client.pubkey(),
+            allocation: None,
+        },
+        AllowlistEntry {
+            wallet: second_client.client.pubkey(),
+            allocation: Some(3),
+        },
+        AllowlistEntry {
+            wallet: Pubkey::new_unique(),
+            allocation: None,
+        },
+    ];
+    let tree = AllowlistTree::new(&entries);
+    let first_proof = tree.proof(0).unwrap();
+    let second_proof = tree.proof(1).unwrap();
+
+    // Only the admin sets the allowlist
+    assert!(market
+        .update_allowlist(ctx, &first_client.client, Some(tree.root()))
+        .await
+        .is_err());
+    market
+        .update_allowlist(ctx, &admin, Some(tree.root()))
+        .await
+        .unwrap();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // Plain buys and proofs of other wallets or allocations are rejected
+    assert!(buy_tokens(ctx, &market, 1, &first_client).await.is_err());
+    assert!(
+        buy_with_proof(ctx, &market, &stranger, 1, None, first_proof.clone())
+            .await
+            .is_err()
+    );
+    assert!(buy_with_proof(
+        ctx,
+        &market,
+        &second_client,
+        1,
+        Some(4),
+        second_proof.clone()
+    )
+    .await
+    .is_err());
+
+    buy_with_proof(ctx, &market, &first_client, 10, None, first_proof)
+        .await
+        .unwrap();
+    assert_eq!(first_client.get_tokens_number(ctx).await, 10);
+
+    // The second client buys at most 3 tokens
+    buy_with_proof(
+        ctx,
+        &market,
+        &second_client,
+        1,
+        Some(3),
+        second_proof.clone(),
+    )
+    .await
+    .unwrap();
+    assert!(buy_with_proof(
+        ctx,
+        &market,
+        &second_client,
+        3,
+        Some(3),
+        second_proof.clone()
+    )
+    .await
+    .is_err());
+    buy_with_proof(ctx, &market, &second_client, 2, Some(3), second_proof)
+        .await
+        .unwrap();
+    assert_eq!(second_client.get_tokens_number(ctx).await, 3);
+
+    // Selling isn't restricted
+    sell_tokens(ctx, &market, 1, &first_client).await.unwrap();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    market.update_allowlist(ctx, &admin, None).await.unwrap();
+    buy_tokens(ctx, &market, 1, &stranger).await.unwrap();
+    assert_eq!(stranger.get_tokens_number(ctx).await, 1);
+}
+
+#[tokio::test]
+async fn blocked_wallet_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 10).await;
+    let other_client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 10).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 5, 10).await;
+    market.add_lamports(ctx, 1_000_000).await;
+
+    // Only the admin blocks wallets
+    assert!(market
+        .block_wallet(ctx, &other_client.client, &client.client.pubkey())
+        .await
+        .is_err());
+    market
+        .block_wallet(ctx, &admin, &client.client.pubkey())
+        .await
+        .unwrap();
+
+    // A wallet can't be blocked twice
+    assert!(market
+        .block_wallet(ctx, &admin, &client.client.pubkey())
+        .await
+        .is_err());
+
+    ////////////////////////////
+    ////////////////////////////
+
+    assert!(buy_tokens(ctx, &market, 1, &client).await.is_err());
+    assert!(sell_tokens(ctx, &market, 1, &client).await.is_err());
+
+    // Other wallets still trade
+    buy_tokens(ctx, &market, 1, &other_client).await.unwrap();
+    sell_tokens(ctx, &market, 2, &other_client).await.unwrap();
+    assert_eq!(other_client.get_tokens_number(ctx).await, 9);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    market
+        .unblock_wallet(ctx, &admin, &client.client.pubkey())
+        .await
+        .unwrap();
+    assert!(market
+        .unblock_wallet(ctx, &admin, &client.client.pubkey())
+        .await
+        .is_err());
+
+    buy_tokens(ctx, &market, 1, &client).await.unwrap();
+    sell_tokens(ctx, &market, 2, &client).await.unwrap();
+    assert_eq!(client.get_tokens_number(ctx).await, 9);
+}
+
+#[tokio::test]
+async fn timelocked_price_transactions() {
+    let ctx = &mut get_context().await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), 10_000_000, 0).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 5, 10).await;
+    // Rent exempt, so warping doesn't collect rent from the market
+    market.add_lamports(ctx, 1_000_000).await;
+
+    assert!(market
+        .update_price_timelock(ctx, &admin, Some(-1))
+        .await
+        .is_err());
+    market
+        .update_price_timelock(ctx, &admin, Some(1000))
+        .await
+        .unwrap();
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The new prices wait for the timelock
+    market.update(ctx, &admin, 50, 100).await.unwrap();
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.buy_price, 10);
+    assert_eq!(settings.pending_price.unwrap().buy_price, 100);
+
+    buy_tokens(ctx, &market, 1, &client).await.unwrap();
+    assert_eq!(client.get_balance(ctx).await, 10_000_000 - 10);
+
+    // A cancelled update never takes effect
+    market.cancel_price_update(ctx, &admin).await.unwrap();
+    assert!(market.cancel_price_update(ctx, &admin).await.is_err());
+
+    ctx.warp_to_slot(100_000).unwrap();
+    buy_tokens(ctx, &market, 2, &client).await.unwrap();
+    assert_eq!(client.get_balance(ctx).await, 10_000_000 - 3 * 10);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    market.update(ctx, &admin, 60, 120).await.unwrap();
+    buy_tokens(ctx, &market, 3, &client).await.unwrap();
+    assert_eq!(client.get_balance(ctx).await, 10_000_000 - 6 * 10);
+
+    ctx.warp_to_slot(200_000).unwrap();
+    buy_tokens(ctx, &market, 1, &client).await.unwrap();
+    assert_eq!(client.get_balance(ctx).await, 10_000_000 - 6 * 10 - 120);
+
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.buy_price, 120);
+    assert_eq!(settings.sell_price, 60);
+    assert_eq!(settings.pending_price, None);
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // A shorter timelock waits for the current one, prices can't be changed at once
+    market
+        .update_price_timelock(ctx, &admin, Some(10))
+        .await
+        .unwrap();
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.price_timelock, Some(1000));
+    assert_eq!(
+        settings.pending_price_timelock.unwrap().price_timelock,
+        Some(10)
+    );
+
+    market.update(ctx, &admin, 70, 140).await.unwrap();
+    buy_tokens(ctx, &market, 2, &client).await.unwrap();
+    assert_eq!(client.get_balance(ctx).await, 10_000_000 - 6 * 10 - 3 * 120);
+
+    // A longer one is set at once and drops the shorter one
+    market
+        .update_price_timelock(ctx, &admin, Some(2000))
+        .await
+        .unwrap();
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.price_timelock, Some(2000));
+    assert_eq!(settings.pending_price_timelock, None);
+
+    // So does the removal
+    market
+        .update_price_timelock(ctx, &admin, None)
+        .await
+        .unwrap();
+    assert_eq!(market.get_settings(ctx).await.price_timelock, Some(2000));
+
+    ctx.warp_to_slot(400_000).unwrap();
+    market.update(ctx, &admin, 80, 160).await.unwrap();
+    let settings = market.get_settings(ctx).await;
+    assert_eq!(settings.price_timelock, None);
+    assert_eq!(settings.pending_price_timelock, None);
+    assert_eq!(settings.buy_price, 160);
+}
+
+#[tokio::test]
+async fn invalid_trade_accounts() {
+    let (foreign_settings, foreign_settings_account) = foreign_settings();
+    let ctx =
+        &mut get_context_with_accounts(vec![(foreign_settings, foreign_settings_account)]).await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let client = TestClient::new(ctx, &mint.pubkey(), 1_000_000, 10).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 10, 20).await;
+    market.add_lamports(ctx, 1_000_000).await;
+
+    let settings = market.get_settings(ctx).await;
+    let client_pubkey = client.client.pubkey();
+    let client_token_pubkey = client.client_token.pubkey();
+    let signers = &[&client.client];
+    let buy = MarketInstructions::buy(
+        &client_pubkey,
+        &client_token_pubkey,
+        None,
+        &settings,
+        TokensNumber(1),
+    );
+    let sell = MarketInstructions::sell(
+        &client_pubkey,
+        &client_token_pubkey,
+        None,
+        &settings,
+        TokensNumber(1),
+    );
+
+    // The client can't pay for the tokens to itself
+    assert_market_error(
+        process_with_account(ctx, buy.clone(), 2, client_pubkey, signers).await,
+        MarketError::LamportsPubkeyMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, sell.clone(), 2, Pubkey::new_unique(), signers).await,
+        MarketError::LamportsPubkeyMismatch,
+    );
+
+    assert_market_error(
+        process_with_account(ctx, buy.clone(), 3, foreign_settings, signers).await,
+        MarketError::SettingsOwnerMismatch,
+    );
+
+    // A blocked client can't pass another wallet's empty record
+    assert_market_error(
+        process_with_account(ctx, buy.clone(), 8, Pubkey::new_unique(), signers).await,
+        MarketError::BlockRecordPubkeyMismatch,
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    assert_market_error(
+        process_with_account(ctx, buy.clone(), 5, Pubkey::new_unique(), signers).await,
+        MarketError::TokenProgramMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, sell.clone(), 5, system_program::id(), signers).await,
+        MarketError::TokenProgramMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, buy.clone(), 6, spl_token::id(), signers).await,
+        MarketError::SystemProgramMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, sell, 6, Pubkey::new_unique(), signers).await,
+        MarketError::SystemProgramMismatch,
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    // The quote's instructions sysvar and nonce record, the last two accounts
+    let quote_key = SecretKey::parse(&[1; 32]).unwrap();
+    market
+        .update_quote_signer(ctx, &admin, Some(quote_signer(&quote_key)))
+        .await
+        .unwrap();
+    let quote = SignedQuote {
+        price: 15,
+        max_tokens: 10,
+        expiry_slot: 1000,
+        nonce: 1,
+    };
+    let verification = sign_quote(&quote_key, &mint.pubkey(), &client_pubkey, &quote);
+    let mut buy_with_quote = MarketInstructions::buy_with_signed_quote(
+        &client_pubkey,
+        &client_token_pubkey,
+        None,
+        &settings,
+        1,
+        quote,
+    );
+    let accounts_number = buy_with_quote.accounts.len();
+
+    buy_with_quote.accounts[accounts_number - 2].pubkey = Pubkey::new_unique();
+    assert_market_error(
+        process_instructions(
+            ctx,
+            &[verification.clone(), buy_with_quote.clone()],
+            signers,
+        )
+        .await,
+        MarketError::InstructionsSysvarMismatch,
+    );
+    buy_with_quote.accounts[accounts_number - 2].pubkey = sysvar::instructions::id();
+    buy_with_quote.accounts[accounts_number - 1].pubkey = Pubkey::new_unique();
+    assert_market_error(
+        process_instructions(ctx, &[verification, buy_with_quote], signers).await,
+        MarketError::NoncePubkeyMismatch,
+    );
+
+    // The buyer record follows the accounts above
+    let limits = SaleLimits {
+        max_tokens_per_buyer: Some(10),
+        ..SaleLimits::default()
+    };
+    market
+        .update_sale_limits(ctx, &admin, limits)
+        .await
+        .unwrap();
+    let settings = market.get_settings(ctx).await;
+    let buy = MarketInstructions::buy(
+        &client_pubkey,
+        &client_token_pubkey,
+        None,
+        &settings,
+        TokensNumber(1),
+    );
+    assert_market_error(
+        process_with_account(ctx, buy, 9, Pubkey::new_unique(), signers).await,
+        MarketError::BuyerRecordPubkeyMismatch,
+    );
+
+    ////////////////////////////
+    ////////////////////////////
+
+    let record_rent = ctx
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(BuyerRecord::LEN);
+    buy_tokens(ctx, &market, 1, &client).await.unwrap();
+    sell_tokens(ctx, &market, 2, &client).await.unwrap();
+    assert_eq!(client.get_tokens_number(ctx).await, 9);
+    assert_eq!(
+        client.get_balance(ctx).await,
+        1_000_000 - 20 + 2 * 10 - record_rent
+    );
+}
+
+#[tokio::test]
+async fn invalid_block_wallet_accounts() {
+    let (foreign_settings, foreign_settings_account) = foreign_settings();
+    let ctx =
+        &mut get_context_with_accounts(vec![(foreign_settings, foreign_settings_account)]).await;
+    let mint = generate_mint(ctx).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+
+    let wallet = Pubkey::new_unique();
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 10, 20).await;
+    let block = MarketInstructions::block_wallet(&admin.pubkey(), &mint.pubkey(), &wallet);
+    let signers = &[&admin];
+
+    assert_market_error(
+        process_with_account(ctx, block.clone(), 1, foreign_settings, signers).await,
+        MarketError::SettingsOwnerMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, block.clone(), 2, Pubkey::new_unique(), signers).await,
+        MarketError::BlockRecordPubkeyMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, block, 3, spl_token::id(), signers).await,
+        MarketError::SystemProgramMismatch,
+    );
+
+    let unblock = MarketInstructions::unblock_wallet(&admin.pubkey(), &mint.pubkey(), &wallet);
+    assert_market_error(
+        process_with_account(ctx, unblock, 2, Pubkey::new_unique(), signers).await,
+        MarketError::BlockRecordPubkeyMismatch,
+    );
+
+    market.block_wallet(ctx, &admin, &wallet).await.unwrap();
 }
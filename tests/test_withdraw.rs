@@ -0,0 +1,162 @@
+use helpers::{
+    client::TestClient,
+    common::{
+        assert_market_error, foreign_settings, generate_mint, get_admin, get_context,
+        get_context_with_accounts, process_with_account,
+    },
+    market::TestMarket,
+};
+use solana_program::{pubkey::Pubkey, system_program};
+use solana_program_test::tokio;
+use solana_sdk::{signature::Keypair, signer::Signer};
+use tokenmarket::{
+    error::MarketError,
+    instruction::{FeeArgs, InitializeArgs, MarketInstructions, PriceArgs},
+    state::{PricingMode, SaleLimits},
+};
+
+mod helpers;
+
+#[tokio::test]
+async fn withdraw_lamports() {
+    let ctx = &mut get_context().await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let mint = generate_mint(ctx).await;
+    let client = TestClient::new(ctx, &mint.pubkey(), 10000, 0).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 10, 20).await;
+    let destination = Keypair::new();
+
+    let lamports = 5000;
+    market.add_lamports(ctx, lamports).await;
+
+    // Only the admin can withdraw
+    assert!(market
+        .withdraw_lamports(ctx, &client.client, &destination.pubkey(), 1000)
+        .await
+        .is_err());
+
+    // Withdrawing nothing is an error
+    assert!(market
+        .withdraw_lamports(ctx, &admin, &destination.pubkey(), 0)
+        .await
+        .is_err());
+
+    // InsufficientFunds
+    assert!(market
+        .withdraw_lamports(ctx, &admin, &destination.pubkey(), lamports + 1)
+        .await
+        .is_err());
+
+    market
+        .withdraw_lamports(ctx, &admin, &destination.pubkey(), 3000)
+        .await
+        .unwrap();
+
+    assert_eq!(market.get_balance(ctx).await, lamports - 3000);
+    assert_eq!(
+        ctx.banks_client
+            .get_balance(destination.pubkey())
+            .await
+            .unwrap(),
+        3000
+    );
+}
+
+#[tokio::test]
+async fn withdraw_lamports_with_reserve() {
+    let ctx = &mut get_context().await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let mint = generate_mint(ctx).await;
+    let destination = Keypair::new();
+
+    let sell_price = 10;
+    let client_tokens = 30;
+    let lamports = 1000;
+
+    // Tokens held outside of the market are the ones it may have to buy back
+    TestClient::new(ctx, &mint.pubkey(), 10000, client_tokens).await;
+    let args = InitializeArgs {
+        prices: PriceArgs {
+            sell_price,
+            buy_price: 20,
+            price_denominator: 1,
+        },
+        fee: FeeArgs {
+            fee_basis_points: 0,
+            fee_recipient: admin.pubkey(),
+        },
+        reserve_sells: true,
+        quote_mint: None,
+        pricing: PricingMode::Fixed,
+        limits: SaleLimits::default(),
+        min_spread_basis_points: 0,
+    };
+    let market = TestMarket::new_with_args(ctx, &admin, &mint.pubkey(), 100, args).await;
+    market.add_lamports(ctx, lamports).await;
+
+    let reserve = sell_price * client_tokens;
+    let available = lamports - reserve;
+
+    assert!(market
+        .withdraw_lamports(ctx, &admin, &destination.pubkey(), available + 1)
+        .await
+        .is_err());
+
+    market
+        .withdraw_lamports(ctx, &admin, &destination.pubkey(), available)
+        .await
+        .unwrap();
+    assert_eq!(market.get_balance(ctx).await, reserve);
+
+    assert!(market
+        .withdraw_lamports(ctx, &admin, &destination.pubkey(), 1)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn withdraw_lamports_invalid_accounts() {
+    let (foreign_settings, foreign_settings_account) = foreign_settings();
+    let ctx =
+        &mut get_context_with_accounts(vec![(foreign_settings, foreign_settings_account)]).await;
+    let admin = get_admin(ctx, 1_000_000_000).await;
+    let mint = generate_mint(ctx).await;
+    let market = TestMarket::new(ctx, &admin, &mint.pubkey(), 100, 10, 20).await;
+    let destination = Keypair::new().pubkey();
+    let signers = &[&admin];
+
+    market.add_lamports(ctx, 5000).await;
+    let withdraw =
+        MarketInstructions::withdraw_lamports(&admin.pubkey(), &mint.pubkey(), &destination, 1000);
+
+    assert_market_error(
+        process_with_account(ctx, withdraw.clone(), 1, foreign_settings, signers).await,
+        MarketError::SettingsOwnerMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, withdraw.clone(), 6, spl_token::id(), signers).await,
+        MarketError::SystemProgramMismatch,
+    );
+    assert_market_error(
+        process_with_account(ctx, withdraw, 8, system_program::id(), signers).await,
+        MarketError::TokenProgramMismatch,
+    );
+    assert_market_error(
+        process_with_account(
+            ctx,
+            MarketInstructions::withdraw_lamports(
+                &admin.pubkey(),
+                &mint.pubkey(),
+                &destination,
+                2000,
+            ),
+            2,
+            Pubkey::new_unique(),
+            signers,
+        )
+        .await,
+        MarketError::LamportsPubkeyMismatch,
+    );
+
+    assert_eq!(market.get_balance(ctx).await, 5000);
+}